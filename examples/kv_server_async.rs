@@ -1,4 +1,4 @@
-use eventloop_async_research::async_rt::{TcpListener, TcpStream};
+use eventloop_async_research::async_rt::{self, TcpListener, TcpStream};
 
 use std::collections::HashMap;
 use std::io;
@@ -15,10 +15,24 @@ async fn main() -> io::Result<()> {
         .unwrap_or_else(|| "127.0.0.1:7070".to_string())
         .parse()
         .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let metrics_addr: Option<SocketAddr> = std::env::args()
+        .nth(3)
+        .map(|a| a.parse())
+        .transpose()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
 
     let listener = TcpListener::bind(addr)?;
     eprintln!("kv server listening on {addr}");
-    eprintln!("usage: cargo run --example kv_server_async -- [poll|epoll] [addr]");
+    eprintln!(
+        "usage: cargo run --example kv_server_async -- [poll|epoll|uring] [addr] [metrics_addr]"
+    );
+
+    // Metrics are opt-in, so two servers can run side by side.
+    if let Some(metrics_addr) = metrics_addr {
+        let metrics = TcpListener::bind(metrics_addr)?;
+        eprintln!("metrics on http://{metrics_addr}/metrics");
+        async_rt::spawn(serve_metrics(metrics));
+    }
 
     let store: Store = Arc::new(Mutex::new(HashMap::new()));
     let next_id = Arc::new(AtomicU64::new(0));
@@ -27,7 +41,7 @@ async fn main() -> io::Result<()> {
         let (stream, peer) = listener.accept().await?;
         let store = store.clone();
         let id = next_id.fetch_add(1, Ordering::Relaxed) + 1;
-        eventloop_async_research::async_rt::spawn(async move {
+        async_rt::spawn(async move {
             eprintln!("[conn#{id}] {peer} connected");
             if let Err(e) = handle_conn(stream, store).await {
                 eprintln!("[conn#{id}] error: {e}");
@@ -37,6 +51,35 @@ async fn main() -> io::Result<()> {
     }
 }
 
+/// Answers every HTTP request with the loop metrics in Prometheus format.
+async fn serve_metrics(listener: TcpListener) {
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                eprintln!("[metrics] accept error: {e}");
+                continue;
+            }
+        };
+        async_rt::spawn(async move {
+            // Read the request head so closing does not reset the connection.
+            let mut head = Vec::new();
+            while !head.windows(4).any(|w| w == b"\r\n\r\n") {
+                match stream.recv_some().await {
+                    Ok(Some(chunk)) => head.extend_from_slice(&chunk),
+                    _ => return,
+                }
+            }
+            let body = async_rt::current_metrics().to_prometheus();
+            let resp = format!(
+                "HTTP/1.0 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\n\r\n{body}",
+                body.len()
+            );
+            let _ = stream.send_all(resp.as_bytes()).await;
+        });
+    }
+}
+
 async fn handle_conn(stream: TcpStream, store: Store) -> io::Result<()> {
     send_line(
         &stream,
@@ -17,17 +17,15 @@ pub fn main(attr: TokenStream, item: TokenStream) -> TokenStream {
     let mut it = item.into_iter().peekable();
 
     let mut leading_attrs: Vec<TokenTree> = Vec::new();
-    loop {
-        let Some(tt) = it.peek() else { break };
-        if matches!(tt, TokenTree::Punct(p) if p.as_char() == '#') {
-            leading_attrs.push(it.next().unwrap());
-            let Some(group) = it.next() else {
-                return compile_error("expected #[..] after #");
-            };
-            leading_attrs.push(group);
-            continue;
+    while let Some(tt) = it.peek() {
+        if !matches!(tt, TokenTree::Punct(p) if p.as_char() == '#') {
+            break;
         }
-        break;
+        leading_attrs.push(it.next().unwrap());
+        let Some(group) = it.next() else {
+            return compile_error("expected #[..] after #");
+        };
+        leading_attrs.push(group);
     }
 
     if matches!(it.peek(), Some(TokenTree::Ident(id)) if id.to_string() == "pub") {
@@ -84,7 +82,7 @@ pub fn main(attr: TokenStream, item: TokenStream) -> TokenStream {
         let _ = it.next();
         let _ = it.next();
 
-        while let Some(tt) = it.next() {
+        for tt in it.by_ref() {
             match tt {
                 TokenTree::Group(g) if g.delimiter() == Delimiter::Brace => {
                     body = Some(g);
@@ -96,7 +94,7 @@ pub fn main(attr: TokenStream, item: TokenStream) -> TokenStream {
     }
 
     if body.is_none() {
-        while let Some(tt) = it.next() {
+        for tt in it {
             match tt {
                 TokenTree::Group(g) if g.delimiter() == Delimiter::Brace => {
                     body = Some(g);
@@ -1,5 +1,6 @@
 use super::context::with_current_loop;
-use crate::runtime::{Interest, IoWatcher, Ready};
+use super::lock;
+use crate::runtime::{Interest, IoMode, IoWatcher, Ready, Token};
 
 use std::future::Future;
 use std::io;
@@ -14,7 +15,8 @@ pub struct AsyncFd {
 }
 
 struct AsyncFdInner {
-    _watcher: IoWatcher,
+    watcher: IoWatcher,
+    mode: IoMode,
     state: Arc<Mutex<AsyncFdState>>,
 }
 
@@ -26,37 +28,89 @@ struct AsyncFdState {
     hup: bool,
     read_waker: Option<Waker>,
     write_waker: Option<Waker>,
+    // Writability is only watched while a writer is parked, otherwise a
+    // level-triggered backend reports every idle socket as writable.
+    write_armed: bool,
+    // Set when a oneshot registration fired and needs re-arming.
+    disarmed: bool,
+    token: Option<Token>,
+}
+
+impl AsyncFdState {
+    fn interest(&self) -> Interest {
+        if self.write_armed {
+            Interest::ReadWrite
+        } else {
+            Interest::Readable
+        }
+    }
 }
 
 impl AsyncFd {
+    /// Registers `fd` level-triggered.
     pub fn new(fd: RawFd) -> io::Result<Self> {
+        Self::with_mode(fd, IoMode::LEVEL)
+    }
+
+    /// Registers `fd` with an explicit `IoMode`. With `IoMode::EDGE` callers
+    /// must retry the operation until it returns `WouldBlock` before awaiting
+    /// readiness again. Oneshot registrations are re-armed whenever a reader
+    /// or writer parks. `IoMode::exclusive` cannot be modified after
+    /// registration, so it only suits read-only fds such as a listener shared
+    /// between loops; `writable` fails on such an fd.
+    pub fn with_mode(fd: RawFd, mode: IoMode) -> io::Result<Self> {
         let state = Arc::new(Mutex::new(AsyncFdState::default()));
         let state_cb = state.clone();
 
         let watcher = with_current_loop(|loop_ref| {
-            loop_ref.watch_io(fd, Interest::ReadWrite, move |_, ready: Ready| {
-                let mut st = state_cb.lock().unwrap();
-                st.readable |= ready.readable;
-                st.writable |= ready.writable;
-                st.error |= ready.error;
-                st.hup |= ready.hup;
-
-                if (st.readable || st.error || st.hup) && st.read_waker.is_some() {
-                    if let Some(w) = st.read_waker.take() {
-                        w.wake();
+            loop_ref.watch_io_with_mode(
+                fd,
+                Interest::Readable,
+                mode,
+                move |loop_ref, ready: Ready| {
+                    let mut st = lock(&state_cb);
+                    st.readable |= ready.readable;
+                    st.writable |= ready.writable;
+                    st.error |= ready.error;
+                    st.hup |= ready.hup;
+
+                    if (st.readable || st.error || st.hup) && st.read_waker.is_some() {
+                        if let Some(w) = st.read_waker.take() {
+                            w.wake();
+                        }
                     }
-                }
-                if (st.writable || st.error || st.hup) && st.write_waker.is_some() {
-                    if let Some(w) = st.write_waker.take() {
-                        w.wake();
+                    if (st.writable || st.error || st.hup) && st.write_waker.is_some() {
+                        if let Some(w) = st.write_waker.take() {
+                            w.wake();
+                        }
                     }
-                }
-            })
+                    let rearm = if st.writable && st.write_armed {
+                        st.write_armed = false;
+                        true
+                    } else {
+                        false
+                    };
+                    let Some(token) = st.token else {
+                        st.disarmed = mode.oneshot;
+                        return;
+                    };
+                    if mode.oneshot {
+                        // A task still parked on the other direction is not
+                        // polled again, so it cannot re-arm by itself.
+                        let parked = st.read_waker.is_some() || st.write_waker.is_some();
+                        st.disarmed = !parked || loop_ref.modify_io(token, st.interest()).is_err();
+                    } else if rearm {
+                        let _ = loop_ref.modify_io(token, Interest::Readable);
+                    }
+                },
+            )
         })?;
+        lock(&state).token = Some(watcher.token());
 
         Ok(Self {
             inner: Arc::new(AsyncFdInner {
-                _watcher: watcher,
+                watcher,
+                mode,
                 state,
             }),
         })
@@ -66,8 +120,16 @@ impl AsyncFd {
         ReadableFuture { afd: self.clone() }.await
     }
 
-    pub async fn writable(&self) -> Ready {
-        WritableFuture { afd: self.clone() }.await
+    /// Fails with `Unsupported` for an `IoMode::exclusive` registration,
+    /// whose interest cannot be widened to writability.
+    pub async fn writable(&self) -> io::Result<Ready> {
+        if self.inner.mode.exclusive {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "exclusive AsyncFd registrations cannot wait for writability",
+            ));
+        }
+        Ok(WritableFuture { afd: self.clone() }.await)
     }
 }
 
@@ -79,7 +141,7 @@ impl Future for ReadableFuture {
     type Output = Ready;
 
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        let mut st = self.afd.inner.state.lock().unwrap();
+        let mut st = lock(&self.afd.inner.state);
         if st.readable || st.error || st.hup {
             let out = Ready {
                 readable: st.readable,
@@ -93,6 +155,12 @@ impl Future for ReadableFuture {
             return Poll::Ready(out);
         }
         st.read_waker = Some(cx.waker().clone());
+        if st.disarmed {
+            st.disarmed = false;
+            let interest = st.interest();
+            drop(st);
+            let _ = self.afd.inner.watcher.set_interest(interest);
+        }
         Poll::Pending
     }
 }
@@ -105,7 +173,7 @@ impl Future for WritableFuture {
     type Output = Ready;
 
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        let mut st = self.afd.inner.state.lock().unwrap();
+        let mut st = lock(&self.afd.inner.state);
         if st.writable || st.error || st.hup {
             let out = Ready {
                 readable: st.readable,
@@ -119,6 +187,78 @@ impl Future for WritableFuture {
             return Poll::Ready(out);
         }
         st.write_waker = Some(cx.waker().clone());
+        if !st.write_armed || st.disarmed {
+            st.write_armed = true;
+            st.disarmed = false;
+            drop(st);
+            let _ = self.afd.inner.watcher.set_interest(Interest::ReadWrite);
+        }
         Poll::Pending
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::async_rt::time::timeout;
+    use crate::BackendKind;
+    use std::io::{Read, Write};
+    use std::os::unix::io::AsRawFd;
+    use std::os::unix::net::UnixStream;
+    use std::time::Duration;
+
+    fn drain(mut stream: &UnixStream) -> usize {
+        let mut buf = [0; 1024];
+        let mut total = 0;
+        loop {
+            match stream.read(&mut buf) {
+                Ok(0) => return total,
+                Ok(n) => total += n,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return total,
+                Err(e) => panic!("read failed: {e}"),
+            }
+        }
+    }
+
+    #[test]
+    fn edge_triggered_drains_until_would_block() {
+        crate::run(BackendKind::Epoll, async {
+            let (a, mut b) = UnixStream::pair().unwrap();
+            a.set_nonblocking(true).unwrap();
+            let afd = AsyncFd::with_mode(a.as_raw_fd(), IoMode::EDGE).unwrap();
+
+            b.write_all(&[7; 3000]).unwrap();
+            afd.readable().await;
+            assert_eq!(drain(&a), 3000);
+
+            // After draining, only new data produces another edge.
+            let short = Duration::from_millis(20);
+            assert!(timeout(short, afd.readable()).await.is_err());
+            b.write_all(&[7; 10]).unwrap();
+            assert!(timeout(Duration::from_secs(1), afd.readable())
+                .await
+                .is_ok());
+            assert_eq!(drain(&a), 10);
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn level_triggered_reports_leftover_data() {
+        crate::run(BackendKind::Epoll, async {
+            let (mut a, mut b) = UnixStream::pair().unwrap();
+            a.set_nonblocking(true).unwrap();
+            let afd = AsyncFd::new(a.as_raw_fd()).unwrap();
+
+            b.write_all(&[7; 3000]).unwrap();
+            afd.readable().await;
+            let mut buf = [0; 1024];
+            assert_eq!(a.read(&mut buf).unwrap(), 1024);
+
+            let wait = Duration::from_secs(1);
+            assert!(timeout(wait, afd.readable()).await.is_ok());
+            assert_eq!(drain(&a), 1976);
+        })
+        .unwrap();
+    }
+}
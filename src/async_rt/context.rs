@@ -1,4 +1,4 @@
-use crate::runtime::EventLoop;
+use crate::runtime::{EventLoop, LoopMetrics};
 
 use std::cell::Cell;
 use std::future::Future;
@@ -37,10 +37,24 @@ pub(crate) fn with_current_loop<R>(f: impl FnOnce(&mut EventLoop) -> R) -> R {
     unsafe { f(&mut *ptr) }
 }
 
+/// Like `with_current_loop`, but `None` outside of a loop's context.
+pub(crate) fn try_with_current_loop<R>(f: impl FnOnce(&mut EventLoop) -> R) -> Option<R> {
+    let ptr = CURRENT_LOOP.with(|c| c.get());
+    if ptr.is_null() {
+        return None;
+    }
+    Some(unsafe { f(&mut *ptr) })
+}
+
 pub fn current_executor() -> super::Executor {
     with_current_loop(|loop_ref| super::Executor::new(loop_ref.handle()))
 }
 
+/// Snapshot of the current loop's metrics, see `EventLoop::metrics`.
+pub fn current_metrics() -> LoopMetrics {
+    with_current_loop(|loop_ref| loop_ref.metrics())
+}
+
 pub fn spawn<F, T>(fut: F) -> super::JoinHandle<T>
 where
     F: Future<Output = T> + Send + 'static,
@@ -48,3 +62,11 @@ where
 {
     current_executor().spawn(fut)
 }
+
+pub fn spawn_named<F, T>(name: impl Into<std::sync::Arc<str>>, fut: F) -> super::JoinHandle<T>
+where
+    F: Future<Output = T> + Send + 'static,
+    T: Send + 'static,
+{
+    current_executor().spawn_named(name, fut)
+}
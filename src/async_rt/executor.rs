@@ -1,12 +1,15 @@
 use super::context::LoopGuard;
-use super::join::{join_state, JoinHandle};
-use crate::runtime::Handle;
+use super::join::{join_state, JoinError, JoinHandle};
+use super::lock;
+use crate::runtime::{Activity, Handle};
 
 use std::future::Future;
-use std::pin::Pin;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::{pin, Pin};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
 use std::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+use std::time::Instant;
 
 #[derive(Clone)]
 pub struct Executor {
@@ -29,30 +32,67 @@ impl Executor {
         F: Future<Output = T> + Send + 'static,
         T: Send + 'static,
     {
+        self.spawn_inner(None, fut)
+    }
+
+    /// Like `spawn`, with a name the watchdog reports the task under.
+    pub fn spawn_named<F, T>(&self, name: impl Into<Arc<str>>, fut: F) -> JoinHandle<T>
+    where
+        F: Future<Output = T> + Send + 'static,
+        T: Send + 'static,
+    {
+        self.spawn_inner(Some(name.into()), fut)
+    }
+
+    fn spawn_inner<F, T>(&self, name: Option<Arc<str>>, fut: F) -> JoinHandle<T>
+    where
+        F: Future<Output = T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let counters = &self.inner.handle.counters;
+        counters.tasks_spawned.fetch_add(1, Ordering::Relaxed);
         let state = join_state::<T>();
         let state2 = state.clone();
         let wrapped = async move {
-            let v = fut.await;
+            // A panicking future is not polled again; the panic goes to the
+            // JoinHandle instead of unwinding into the loop.
+            let mut fut = pin!(fut);
+            let res = std::future::poll_fn(|cx| {
+                match panic::catch_unwind(AssertUnwindSafe(|| fut.as_mut().poll(cx))) {
+                    Ok(Poll::Ready(v)) => Poll::Ready(Ok(v)),
+                    Ok(Poll::Pending) => Poll::Pending,
+                    Err(payload) => Poll::Ready(Err(JoinError::Panic(payload))),
+                }
+            })
+            .await;
             if state2.cancelled.load(Ordering::Acquire) {
                 return;
             }
-            *state2.result.lock().unwrap() = Some(v);
+            *lock(&state2.result) = Some(res);
             state2.done.store(true, Ordering::Release);
-            if let Some(w) = state2.waker.lock().unwrap().take() {
+            if let Some(w) = lock(&state2.waker).take() {
                 w.wake();
             }
         };
 
         let task = Arc::new(Task {
+            id: NEXT_TASK_ID.fetch_add(1, Ordering::Relaxed),
+            name,
             fut: Mutex::new(Some(Box::pin(wrapped))),
             scheduled: AtomicBool::new(false),
             done: AtomicBool::new(false),
             cancelled: AtomicBool::new(false),
         });
-        self.schedule(task.clone());
+        task.scheduled.store(true, Ordering::Release);
+        self.post_poll(task.clone(), true);
         JoinHandle::new(self.clone(), task, state)
     }
 
+    fn task_completed(&self) {
+        let counters = &self.inner.handle.counters;
+        counters.tasks_completed.fetch_add(1, Ordering::Relaxed);
+    }
+
     pub(crate) fn schedule(&self, task: Arc<Task>) {
         if task.done.load(Ordering::Acquire) {
             return;
@@ -60,17 +100,50 @@ impl Executor {
         if task.scheduled.swap(true, Ordering::AcqRel) {
             return;
         }
+        self.post_poll(task, false);
+    }
 
+    /// Queues a poll of `task`; `spawned` marks the first one, posted by
+    /// `spawn` itself, which is when observers hear about the task.
+    fn post_poll(&self, task: Arc<Task>, spawned: bool) {
         let exec = self.clone();
-        let _ = self.inner.handle.post(move |loop_ref| {
+        let _ = self.inner.handle.post_unbounded(move |loop_ref| {
             let _guard = LoopGuard::enter(loop_ref as *mut _);
             task.scheduled.store(false, Ordering::Release);
-            task.poll(&exec);
+            if spawned {
+                loop_ref.observe(|o| o.task_spawned(task.id));
+            }
+            let activity = || Activity::Spawned {
+                id: task.id,
+                name: task.name.clone(),
+            };
+            if !loop_ref.is_observed() {
+                if loop_ref.track(activity, |_| task.poll(&exec)) {
+                    exec.task_completed();
+                }
+                return;
+            }
+            let start = Instant::now();
+            let finished = loop_ref.track(activity, |_| task.poll(&exec));
+            let took = start.elapsed();
+            if finished {
+                exec.task_completed();
+            }
+            loop_ref.observe(|o| {
+                o.task_polled(task.id, took);
+                if finished {
+                    o.task_completed(task.id);
+                }
+            });
         });
     }
 }
 
+static NEXT_TASK_ID: AtomicU64 = AtomicU64::new(0);
+
 pub(crate) struct Task {
+    id: u64,
+    name: Option<Arc<str>>,
     fut: Mutex<Option<Pin<Box<dyn Future<Output = ()> + Send + 'static>>>>,
     scheduled: AtomicBool,
     done: AtomicBool,
@@ -78,31 +151,35 @@ pub(crate) struct Task {
 }
 
 impl Task {
-    fn poll(self: &Arc<Self>, exec: &Executor) {
+    /// Returns whether the task finished during this call.
+    fn poll(self: &Arc<Self>, exec: &Executor) -> bool {
         if self.done.load(Ordering::Acquire) {
-            return;
+            return false;
         }
         if self.cancelled.load(Ordering::Acquire) {
             self.done.store(true, Ordering::Release);
-            let _ = self.fut.lock().unwrap().take();
-            return;
+            // Drop the future outside the lock, its destructor may panic.
+            let fut = lock(&self.fut).take();
+            drop(fut);
+            return true;
         }
 
         let waker = task_waker(exec.clone(), self.clone());
         let mut cx = Context::from_waker(&waker);
 
-        let mut fut = self.fut.lock().unwrap();
+        let mut fut = lock(&self.fut);
         let Some(fut_inner) = fut.as_mut() else {
             self.done.store(true, Ordering::Release);
-            return;
+            return true;
         };
         let poll_res = fut_inner.as_mut().poll(&mut cx);
         match poll_res {
             Poll::Ready(()) => {
                 self.done.store(true, Ordering::Release);
                 let _ = fut.take();
+                true
             }
-            Poll::Pending => {}
+            Poll::Pending => false,
         }
     }
 
@@ -1,19 +1,42 @@
 use super::executor::Task;
-use super::Executor;
+use super::{lock, Executor};
 
+use std::any::Any;
 use std::future::Future;
 use std::pin::Pin;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
 use std::task::{Context, Poll, Waker};
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug)]
 pub enum JoinError {
     Cancelled,
+    /// The task panicked; holds the panic payload.
+    Panic(Box<dyn Any + Send>),
+}
+
+impl JoinError {
+    pub fn is_cancelled(&self) -> bool {
+        matches!(self, JoinError::Cancelled)
+    }
+
+    pub fn is_panic(&self) -> bool {
+        matches!(self, JoinError::Panic(_))
+    }
+
+    /// Returns the panic payload, e.g. to `std::panic::resume_unwind` it.
+    ///
+    /// Panics if the task was cancelled instead.
+    pub fn into_panic(self) -> Box<dyn Any + Send> {
+        match self {
+            JoinError::Panic(payload) => payload,
+            JoinError::Cancelled => panic!("task was cancelled, not panicked"),
+        }
+    }
 }
 
 pub(crate) struct JoinState<T> {
-    pub(crate) result: Mutex<Option<T>>,
+    pub(crate) result: Mutex<Option<Result<T, JoinError>>>,
     pub(crate) done: AtomicBool,
     pub(crate) cancelled: AtomicBool,
     pub(crate) waker: Mutex<Option<Waker>>,
@@ -38,7 +61,7 @@ impl<T> JoinHandle<T> {
         self.state.cancelled.store(true, Ordering::Release);
         self.task.cancel();
         self.exec.schedule(self.task.clone());
-        if let Some(w) = self.state.waker.lock().unwrap().take() {
+        if let Some(w) = lock(&self.state.waker).take() {
             w.wake();
         }
     }
@@ -56,12 +79,12 @@ impl<T: Send + 'static> Future for JoinHandle<T> {
             return Poll::Ready(Err(JoinError::Cancelled));
         }
 
-        if let Some(v) = self.state.result.lock().unwrap().take() {
+        if let Some(res) = lock(&self.state.result).take() {
             self.state.done.store(true, Ordering::Release);
-            return Poll::Ready(Ok(v));
+            return Poll::Ready(res);
         }
 
-        *self.state.waker.lock().unwrap() = Some(cx.waker().clone());
+        *lock(&self.state.waker) = Some(cx.waker().clone());
         Poll::Pending
     }
 }
@@ -3,15 +3,30 @@ mod context;
 mod executor;
 mod join;
 mod net;
+pub mod process;
 mod queue;
+pub mod signal;
 mod task_group;
 mod time;
+#[cfg(target_os = "linux")]
+pub mod uring;
 
 pub use async_fd::AsyncFd;
-pub use context::{current_executor, spawn};
+pub(crate) use context::try_with_current_loop;
+pub use context::{current_executor, current_metrics, spawn, spawn_named};
 pub use executor::Executor;
 pub use join::{join_all, select2, select_any, JoinError, JoinHandle, Select2, SelectAny};
 pub use net::{TcpListener, TcpStream};
 pub use queue::AsyncQueue;
 pub use task_group::TaskGroup;
-pub use time::{sleep, Sleep};
+pub use time::{
+    interval, sleep, sleep_until, timeout, timeout_at, Elapsed, Interval, Sleep, Timeout,
+};
+
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+/// Locks `m` even if a panic poisoned it. Panics are caught at task and
+/// callback boundaries, so the loop keeps using shared state afterwards.
+pub(crate) fn lock<T>(m: &Mutex<T>) -> MutexGuard<'_, T> {
+    m.lock().unwrap_or_else(PoisonError::into_inner)
+}
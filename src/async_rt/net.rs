@@ -1,4 +1,5 @@
 use super::async_fd::AsyncFd;
+use super::lock;
 
 use std::io;
 use std::io::{Read, Write};
@@ -32,7 +33,7 @@ impl TcpListener {
 
     pub async fn accept(&self) -> io::Result<(TcpStream, SocketAddr)> {
         loop {
-            let res = self.inner.listener.lock().unwrap().accept();
+            let res = lock(&self.inner.listener).accept();
             match res {
                 Ok((stream, addr)) => {
                     stream.set_nonblocking(true)?;
@@ -74,7 +75,7 @@ impl TcpStream {
     pub async fn recv_some(&self) -> io::Result<Option<Vec<u8>>> {
         let mut buf = vec![0u8; 4096];
         loop {
-            let res = self.inner.stream.lock().unwrap().read(&mut buf);
+            let res = lock(&self.inner.stream).read(&mut buf);
             match res {
                 Ok(0) => return Ok(None),
                 Ok(n) => {
@@ -93,7 +94,7 @@ impl TcpStream {
     pub async fn send_all(&self, data: &[u8]) -> io::Result<()> {
         let mut offset = 0;
         while offset < data.len() {
-            let res = self.inner.stream.lock().unwrap().write(&data[offset..]);
+            let res = lock(&self.inner.stream).write(&data[offset..]);
             match res {
                 Ok(0) => {
                     return Err(io::Error::new(
@@ -103,7 +104,7 @@ impl TcpStream {
                 }
                 Ok(n) => offset += n,
                 Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
-                    let _ = self.inner.afd.writable().await;
+                    self.inner.afd.writable().await?;
                 }
                 Err(e) => return Err(e),
             }
@@ -0,0 +1,292 @@
+use super::async_fd::AsyncFd;
+use super::signal::{signal, Signal, SignalKind};
+use crate::runtime::set_nonblocking;
+
+use std::ffi::OsStr;
+use std::io::{self, Read, Write};
+use std::os::unix::io::{AsRawFd, OwnedFd};
+use std::path::Path;
+use std::process::{ExitStatus, Stdio};
+
+/// Async wrapper around `std::process::Command`.
+///
+/// `spawn` must run inside the event loop: piped stdio and the exit watcher
+/// are registered with it.
+pub struct Command {
+    inner: std::process::Command,
+}
+
+impl Command {
+    pub fn new<S: AsRef<OsStr>>(program: S) -> Self {
+        Self {
+            inner: std::process::Command::new(program),
+        }
+    }
+
+    pub fn arg<S: AsRef<OsStr>>(&mut self, arg: S) -> &mut Self {
+        self.inner.arg(arg);
+        self
+    }
+
+    pub fn args<I, S>(&mut self, args: I) -> &mut Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        self.inner.args(args);
+        self
+    }
+
+    pub fn env<K, V>(&mut self, key: K, val: V) -> &mut Self
+    where
+        K: AsRef<OsStr>,
+        V: AsRef<OsStr>,
+    {
+        self.inner.env(key, val);
+        self
+    }
+
+    pub fn current_dir<P: AsRef<Path>>(&mut self, dir: P) -> &mut Self {
+        self.inner.current_dir(dir);
+        self
+    }
+
+    pub fn stdin<T: Into<Stdio>>(&mut self, cfg: T) -> &mut Self {
+        self.inner.stdin(cfg);
+        self
+    }
+
+    pub fn stdout<T: Into<Stdio>>(&mut self, cfg: T) -> &mut Self {
+        self.inner.stdout(cfg);
+        self
+    }
+
+    pub fn stderr<T: Into<Stdio>>(&mut self, cfg: T) -> &mut Self {
+        self.inner.stderr(cfg);
+        self
+    }
+
+    pub fn as_std_mut(&mut self) -> &mut std::process::Command {
+        &mut self.inner
+    }
+
+    pub fn spawn(&mut self) -> io::Result<Child> {
+        let mut child = self.inner.spawn()?;
+        let stdin = child.stdin.take().map(ChildStdin::new).transpose()?;
+        let stdout = child.stdout.take().map(ChildStdout::new).transpose()?;
+        let stderr = child.stderr.take().map(ChildStderr::new).transpose()?;
+        Ok(Child {
+            inner: child,
+            stdin,
+            stdout,
+            stderr,
+            exit: None,
+        })
+    }
+
+    pub async fn status(&mut self) -> io::Result<ExitStatus> {
+        self.spawn()?.wait().await
+    }
+}
+
+impl From<std::process::Command> for Command {
+    fn from(inner: std::process::Command) -> Self {
+        Self { inner }
+    }
+}
+
+pub struct Child {
+    inner: std::process::Child,
+    pub stdin: Option<ChildStdin>,
+    pub stdout: Option<ChildStdout>,
+    pub stderr: Option<ChildStderr>,
+    exit: Option<ExitWatch>,
+}
+
+impl Child {
+    pub fn id(&self) -> u32 {
+        self.inner.id()
+    }
+
+    pub fn kill(&mut self) -> io::Result<()> {
+        self.inner.kill()
+    }
+
+    pub fn try_wait(&mut self) -> io::Result<Option<ExitStatus>> {
+        self.inner.try_wait()
+    }
+
+    /// Waits for the child to exit. Closes `stdin` first, like
+    /// `std::process::Child::wait`, so a child reading it sees EOF.
+    pub async fn wait(&mut self) -> io::Result<ExitStatus> {
+        drop(self.stdin.take());
+        loop {
+            if let Some(status) = self.inner.try_wait()? {
+                self.exit = None;
+                return Ok(status);
+            }
+            let Some(exit) = self.exit.as_mut() else {
+                // Check again once armed, the child may exit in between.
+                self.exit = Some(ExitWatch::new(self.inner.id())?);
+                continue;
+            };
+            match exit {
+                ExitWatch::Pidfd { afd, .. } => {
+                    afd.readable().await;
+                }
+                ExitWatch::Sigchld(sig) => sig.recv().await,
+            }
+        }
+    }
+}
+
+enum ExitWatch {
+    // A pidfd turns readable once the process exits.
+    Pidfd { afd: AsyncFd, _fd: OwnedFd },
+    Sigchld(Signal),
+}
+
+impl ExitWatch {
+    fn new(pid: u32) -> io::Result<Self> {
+        #[cfg(target_os = "linux")]
+        match pidfd_open(pid) {
+            Ok(fd) => {
+                let afd = AsyncFd::new(fd.as_raw_fd())?;
+                return Ok(Self::Pidfd { afd, _fd: fd });
+            }
+            // Linux before 5.3.
+            Err(e) if e.raw_os_error() == Some(ENOSYS) => {}
+            Err(e) => return Err(e),
+        }
+        #[cfg(not(target_os = "linux"))]
+        let _ = pid;
+        Ok(Self::Sigchld(signal(SignalKind::CHILD)?))
+    }
+}
+
+#[cfg(target_os = "linux")]
+const ENOSYS: i32 = 38;
+
+#[cfg(target_os = "linux")]
+fn pidfd_open(pid: u32) -> io::Result<OwnedFd> {
+    use std::os::raw::c_long;
+    use std::os::unix::io::FromRawFd;
+
+    extern "C" {
+        fn syscall(num: c_long, ...) -> c_long;
+    }
+    const SYS_PIDFD_OPEN: c_long = 434;
+
+    let rc = unsafe { syscall(SYS_PIDFD_OPEN, pid as c_long, 0 as c_long) };
+    if rc < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { OwnedFd::from_raw_fd(rc as i32) })
+}
+
+pub struct ChildStdin {
+    inner: std::process::ChildStdin,
+    afd: AsyncFd,
+}
+
+impl ChildStdin {
+    fn new(inner: std::process::ChildStdin) -> io::Result<Self> {
+        set_nonblocking(inner.as_raw_fd())?;
+        let afd = AsyncFd::new(inner.as_raw_fd())?;
+        Ok(Self { inner, afd })
+    }
+
+    pub async fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        loop {
+            match self.inner.write(buf) {
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    self.afd.writable().await?;
+                }
+                res => return res,
+            }
+        }
+    }
+
+    pub async fn write_all(&mut self, mut buf: &[u8]) -> io::Result<()> {
+        while !buf.is_empty() {
+            match self.write(buf).await? {
+                0 => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::WriteZero,
+                        "pipe write returned 0",
+                    ))
+                }
+                n => buf = &buf[n..],
+            }
+        }
+        Ok(())
+    }
+}
+
+pub struct ChildStdout {
+    inner: std::process::ChildStdout,
+    afd: AsyncFd,
+}
+
+impl ChildStdout {
+    fn new(inner: std::process::ChildStdout) -> io::Result<Self> {
+        set_nonblocking(inner.as_raw_fd())?;
+        let afd = AsyncFd::new(inner.as_raw_fd())?;
+        Ok(Self { inner, afd })
+    }
+
+    pub async fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        read_pipe(&mut self.inner, &self.afd, buf).await
+    }
+
+    pub async fn read_to_end(&mut self, buf: &mut Vec<u8>) -> io::Result<usize> {
+        read_pipe_to_end(&mut self.inner, &self.afd, buf).await
+    }
+}
+
+pub struct ChildStderr {
+    inner: std::process::ChildStderr,
+    afd: AsyncFd,
+}
+
+impl ChildStderr {
+    fn new(inner: std::process::ChildStderr) -> io::Result<Self> {
+        set_nonblocking(inner.as_raw_fd())?;
+        let afd = AsyncFd::new(inner.as_raw_fd())?;
+        Ok(Self { inner, afd })
+    }
+
+    pub async fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        read_pipe(&mut self.inner, &self.afd, buf).await
+    }
+
+    pub async fn read_to_end(&mut self, buf: &mut Vec<u8>) -> io::Result<usize> {
+        read_pipe_to_end(&mut self.inner, &self.afd, buf).await
+    }
+}
+
+async fn read_pipe<R: Read>(pipe: &mut R, afd: &AsyncFd, buf: &mut [u8]) -> io::Result<usize> {
+    loop {
+        match pipe.read(buf) {
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                let _ = afd.readable().await;
+            }
+            res => return res,
+        }
+    }
+}
+
+async fn read_pipe_to_end<R: Read>(
+    pipe: &mut R,
+    afd: &AsyncFd,
+    buf: &mut Vec<u8>,
+) -> io::Result<usize> {
+    let start = buf.len();
+    let mut chunk = [0u8; 4096];
+    loop {
+        match read_pipe(pipe, afd, &mut chunk).await? {
+            0 => return Ok(buf.len() - start),
+            n => buf.extend_from_slice(&chunk[..n]),
+        }
+    }
+}
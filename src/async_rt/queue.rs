@@ -1,3 +1,5 @@
+use super::lock;
+
 use std::collections::VecDeque;
 use std::future::Future;
 use std::pin::Pin;
@@ -27,7 +29,7 @@ impl<T> AsyncQueue<T> {
     }
 
     pub fn push(&self, item: T) {
-        let mut st = self.inner.lock().unwrap();
+        let mut st = lock(&self.inner);
         if st.closed {
             return;
         }
@@ -38,7 +40,7 @@ impl<T> AsyncQueue<T> {
     }
 
     pub fn close(&self) {
-        let mut st = self.inner.lock().unwrap();
+        let mut st = lock(&self.inner);
         st.closed = true;
         if let Some(w) = st.waiter.take() {
             w.wake();
@@ -55,6 +57,12 @@ impl<T> AsyncQueue<T> {
     }
 }
 
+impl<T> Default for AsyncQueue<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 struct PopFuture<T> {
     q: AsyncQueue<T>,
 }
@@ -63,7 +71,7 @@ impl<T> Future for PopFuture<T> {
     type Output = Option<T>;
 
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        let mut st = self.q.inner.lock().unwrap();
+        let mut st = lock(&self.q.inner);
         if let Some(v) = st.buf.pop_front() {
             return Poll::Ready(Some(v));
         }
@@ -0,0 +1,82 @@
+use super::context::with_current_loop;
+use super::lock;
+use crate::runtime::{Handle, SignalId};
+
+pub use crate::runtime::SignalKind;
+
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+
+/// Listens for `kind` on the current loop until the returned `Signal` is
+/// dropped. Deliveries that arrive while nobody waits are coalesced into one.
+pub fn signal(kind: SignalKind) -> io::Result<Signal> {
+    let state = Arc::new(Mutex::new(SignalState::default()));
+    let state_cb = state.clone();
+    with_current_loop(|loop_ref| {
+        let id = loop_ref.add_signal(kind.as_raw(), move |_| {
+            let mut st = lock(&state_cb);
+            st.received = true;
+            if let Some(w) = st.waker.take() {
+                w.wake();
+            }
+        })?;
+        Ok(Signal {
+            id,
+            state,
+            handle: loop_ref.handle(),
+        })
+    })
+}
+
+pub struct Signal {
+    id: SignalId,
+    state: Arc<Mutex<SignalState>>,
+    handle: Handle,
+}
+
+#[derive(Default)]
+struct SignalState {
+    received: bool,
+    waker: Option<Waker>,
+}
+
+impl Signal {
+    /// Completes on the next delivery of the signal.
+    pub async fn recv(&mut self) {
+        RecvFuture { signal: self }.await
+    }
+
+    fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        let mut st = lock(&self.state);
+        if st.received {
+            st.received = false;
+            return Poll::Ready(());
+        }
+        st.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl Drop for Signal {
+    fn drop(&mut self) {
+        let id = self.id;
+        let _ = self.handle.post_unbounded(move |loop_ref| {
+            loop_ref.remove_signal(id);
+        });
+    }
+}
+
+struct RecvFuture<'a> {
+    signal: &'a mut Signal,
+}
+
+impl Future for RecvFuture<'_> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        self.signal.poll_recv(cx)
+    }
+}
@@ -1,4 +1,4 @@
-use super::Executor;
+use super::{lock, Executor};
 
 use std::future::Future;
 use std::pin::Pin;
@@ -35,12 +35,9 @@ impl TaskGroup {
         let st = self.inner.clone();
         let exec = st.exec.clone();
         exec.spawn(async move {
+            // Counts the task as finished even if it panics.
+            let _finished = Finished(st);
             fut.await;
-            if st.active.fetch_sub(1, Ordering::AcqRel) == 1 {
-                if let Some(w) = st.join_waker.lock().unwrap().take() {
-                    w.wake();
-                }
-            }
         });
     }
 
@@ -52,6 +49,18 @@ impl TaskGroup {
     }
 }
 
+struct Finished(Arc<TaskGroupInner>);
+
+impl Drop for Finished {
+    fn drop(&mut self) {
+        if self.0.active.fetch_sub(1, Ordering::AcqRel) == 1 {
+            if let Some(w) = lock(&self.0.join_waker).take() {
+                w.wake();
+            }
+        }
+    }
+}
+
 struct JoinFuture {
     st: Arc<TaskGroupInner>,
 }
@@ -63,7 +72,7 @@ impl Future for JoinFuture {
         if self.st.active.load(Ordering::Acquire) == 0 {
             return Poll::Ready(());
         }
-        *self.st.join_waker.lock().unwrap() = Some(cx.waker().clone());
+        *lock(&self.st.join_waker) = Some(cx.waker().clone());
         Poll::Pending
     }
 }
@@ -1,26 +1,33 @@
 use super::context::with_current_loop;
+use super::lock;
+use crate::runtime::{MissedTickBehavior, TimerHandle};
 
 use std::future::Future;
+use std::io;
 use std::pin::Pin;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
 use std::task::{Context, Poll, Waker};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 pub fn sleep(delay: Duration) -> Sleep {
+    sleep_until(Instant::now() + delay)
+}
+
+pub fn sleep_until(deadline: Instant) -> Sleep {
     Sleep {
-        delay,
+        deadline,
         fired: Arc::new(AtomicBool::new(false)),
         waker: Arc::new(Mutex::new(None)),
-        started: false,
+        timer: None,
     }
 }
 
 pub struct Sleep {
-    delay: Duration,
+    deadline: Instant,
     fired: Arc<AtomicBool>,
     waker: Arc<Mutex<Option<Waker>>>,
-    started: bool,
+    timer: Option<TimerHandle>,
 }
 
 impl Future for Sleep {
@@ -31,23 +38,221 @@ impl Future for Sleep {
             return Poll::Ready(());
         }
 
-        *self.waker.lock().unwrap() = Some(cx.waker().clone());
+        *lock(&self.waker) = Some(cx.waker().clone());
 
-        if !self.started {
-            self.started = true;
+        if self.timer.is_none() {
             let fired = self.fired.clone();
             let waker = self.waker.clone();
-            let delay = self.delay;
-            with_current_loop(|loop_ref| {
-                loop_ref.post_delayed(delay, move |_| {
+            let deadline = self.deadline;
+            let timer = with_current_loop(|loop_ref| {
+                loop_ref.post_at(deadline, move |_| {
                     fired.store(true, Ordering::Release);
-                    if let Some(w) = waker.lock().unwrap().take() {
+                    if let Some(w) = lock(&waker).take() {
                         w.wake();
                     }
-                });
+                })
             });
+            self.timer = Some(timer);
         }
 
         Poll::Pending
     }
 }
+
+impl Sleep {
+    pub fn deadline(&self) -> Instant {
+        self.deadline
+    }
+
+    pub fn is_elapsed(&self) -> bool {
+        self.fired.load(Ordering::Acquire)
+    }
+
+    fn cancel(&mut self) {
+        if self.fired.load(Ordering::Acquire) {
+            return;
+        }
+        if let Some(timer) = self.timer.take() {
+            let _ = timer.cancel();
+        }
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        self.cancel();
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Elapsed;
+
+impl std::fmt::Display for Elapsed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("deadline has elapsed")
+    }
+}
+
+impl std::error::Error for Elapsed {}
+
+impl From<Elapsed> for io::Error {
+    fn from(e: Elapsed) -> Self {
+        io::Error::new(io::ErrorKind::TimedOut, e)
+    }
+}
+
+/// Bounds `fut` by `delay`. The timer is cancelled as soon as `fut` completes.
+pub fn timeout<F: Future>(delay: Duration, fut: F) -> Timeout<F> {
+    timeout_at(Instant::now() + delay, fut)
+}
+
+pub fn timeout_at<F: Future>(deadline: Instant, fut: F) -> Timeout<F> {
+    Timeout {
+        fut: Box::pin(fut),
+        sleep: sleep_until(deadline),
+    }
+}
+
+pub struct Timeout<F: Future> {
+    fut: Pin<Box<F>>,
+    sleep: Sleep,
+}
+
+impl<F: Future> Future for Timeout<F> {
+    type Output = Result<F::Output, Elapsed>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if let Poll::Ready(v) = self.fut.as_mut().poll(cx) {
+            self.sleep.cancel();
+            return Poll::Ready(Ok(v));
+        }
+        if Pin::new(&mut self.sleep).poll(cx).is_ready() {
+            return Poll::Ready(Err(Elapsed));
+        }
+        Poll::Pending
+    }
+}
+
+/// Creates an `Interval` whose first tick completes immediately and whose
+/// following ticks complete every `period`.
+pub fn interval(period: Duration) -> Interval {
+    assert!(!period.is_zero(), "interval: period must be non-zero");
+    Interval {
+        period,
+        behavior: MissedTickBehavior::Burst,
+        state: Arc::new(Mutex::new(IntervalState {
+            pending: 0,
+            oldest: Instant::now(),
+            latest: Instant::now(),
+            waker: None,
+        })),
+        timer: None,
+    }
+}
+
+// Driven by one repeating loop timer, which re-arms its entry in the loop's
+// timer queue after each firing instead of the task posting a new timer per
+// tick; missed ticks are handled by the timer's `MissedTickBehavior`.
+pub struct Interval {
+    period: Duration,
+    behavior: MissedTickBehavior,
+    state: Arc<Mutex<IntervalState>>,
+    timer: Option<TimerHandle>,
+}
+
+struct IntervalState {
+    // Ticks fired and not yet returned. A count rather than a queue, so an
+    // interval nobody polls does not grow.
+    pending: u64,
+    // Fire times of the oldest pending tick and of the latest one.
+    oldest: Instant,
+    latest: Instant,
+    waker: Option<Waker>,
+}
+
+impl Interval {
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+
+    pub fn missed_tick_behavior(&self) -> MissedTickBehavior {
+        self.behavior
+    }
+
+    /// Takes effect for ticks that have not been scheduled yet, so set it
+    /// before the first `tick`.
+    pub fn set_missed_tick_behavior(&mut self, behavior: MissedTickBehavior) {
+        self.behavior = behavior;
+    }
+
+    pub async fn tick(&mut self) -> Instant {
+        TickFuture { interval: self }.await
+    }
+
+    fn poll_tick(&mut self, cx: &mut Context<'_>) -> Poll<Instant> {
+        if self.timer.is_none() {
+            let state = self.state.clone();
+            let behavior = self.behavior;
+            let timer = with_current_loop(|loop_ref| {
+                loop_ref.post_repeating_with(self.period, behavior, move |_| {
+                    let mut st = lock(&state);
+                    let now = Instant::now();
+                    // A slow consumer only ever sees one pending tick unless
+                    // it asked for bursts.
+                    if st.pending == 0 || behavior != MissedTickBehavior::Burst {
+                        st.pending = 0;
+                        st.oldest = now;
+                    }
+                    st.pending = st.pending.saturating_add(1);
+                    st.latest = now;
+                    if let Some(w) = st.waker.take() {
+                        w.wake();
+                    }
+                })
+            });
+            self.timer = Some(timer);
+            return Poll::Ready(Instant::now());
+        }
+
+        let mut st = lock(&self.state);
+        if st.pending > 0 {
+            let at = st.oldest;
+            st.pending -= 1;
+            // Burst ticks only keep their count; space the remaining ones a
+            // period apart, but never past the latest firing.
+            st.oldest = (at + self.period).min(st.latest);
+            drop(st);
+            let now = Instant::now();
+            if self.behavior == MissedTickBehavior::Delay && now >= at + self.period {
+                if let Some(timer) = &self.timer {
+                    let id = timer.id();
+                    let period = self.period;
+                    with_current_loop(|loop_ref| loop_ref.reset_timer(id, now + period));
+                }
+            }
+            return Poll::Ready(at);
+        }
+        st.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl Drop for Interval {
+    fn drop(&mut self) {
+        if let Some(timer) = self.timer.take() {
+            let _ = timer.cancel();
+        }
+    }
+}
+
+struct TickFuture<'a> {
+    interval: &'a mut Interval,
+}
+
+impl Future for TickFuture<'_> {
+    type Output = Instant;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Instant> {
+        self.interval.poll_tick(cx)
+    }
+}
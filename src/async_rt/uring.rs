@@ -0,0 +1,486 @@
+//! Completion-based I/O on io_uring.
+//!
+//! Each `EventLoop` lazily gets its own ring whose fd is watched through
+//! `add_io`, so these operations work with any readiness backend. Buffers are
+//! passed by value and handed back with the result: if a future is dropped
+//! while its operation is in flight, the buffer stays with the ring until the
+//! kernel is done with it and the operation is cancelled.
+
+use super::context::with_current_loop;
+use super::lock;
+use crate::runtime::os::uring::{
+    Ring, Sqe, IORING_OP_ACCEPT, IORING_OP_ASYNC_CANCEL, IORING_OP_CONNECT, IORING_OP_FSYNC,
+    IORING_OP_OPENAT, IORING_OP_READ, IORING_OP_WRITE,
+};
+use crate::runtime::Interest;
+
+use std::any::Any;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::future::Future;
+use std::io;
+use std::marker::PhantomData;
+use std::net::SocketAddr;
+use std::os::raw::c_int;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{FromRawFd, OwnedFd, RawFd};
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, Weak};
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
+
+pub const AT_FDCWD: RawFd = -100;
+
+pub const O_RDONLY: i32 = 0;
+pub const O_WRONLY: i32 = 0o1;
+pub const O_RDWR: i32 = 0o2;
+pub const O_CREAT: i32 = 0o100;
+pub const O_TRUNC: i32 = 0o1000;
+pub const O_APPEND: i32 = 0o2000;
+
+const O_CLOEXEC: i32 = 0o2000000;
+const SOCK_CLOEXEC: u32 = 0o2000000;
+
+const AF_INET: u16 = 2;
+const AF_INET6: u16 = 10;
+
+// Tags cancel requests, whose own completions carry nothing of interest.
+const CANCEL_USER_DATA: u64 = u64::MAX;
+
+// How long dropping a loop waits for cancelled operations to complete.
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(1);
+
+extern "C" {
+    fn close(fd: c_int) -> c_int;
+}
+
+/// Reads into the spare capacity of `buf`, appending to its contents.
+/// `offset` of `None` reads from the current file position.
+pub async fn read(
+    fd: RawFd,
+    mut buf: Vec<u8>,
+    offset: Option<u64>,
+) -> (io::Result<usize>, Vec<u8>) {
+    let spare = buf.capacity() - buf.len();
+    let ptr = unsafe { buf.as_mut_ptr().add(buf.len()) };
+    let sqe = Sqe {
+        opcode: IORING_OP_READ,
+        fd,
+        addr: ptr as u64,
+        len: spare.min(u32::MAX as usize) as u32,
+        off: offset.unwrap_or(u64::MAX),
+        ..Sqe::default()
+    };
+    let (res, mut buf) = match Op::submit(sqe, buf, false) {
+        Ok(op) => op.await,
+        Err((e, buf)) => return (Err(e), buf),
+    };
+    match res {
+        Ok(n) => {
+            // The kernel initialized `n` bytes past the old length.
+            unsafe { buf.set_len(buf.len() + n as usize) };
+            (Ok(n as usize), buf)
+        }
+        Err(e) => (Err(e), buf),
+    }
+}
+
+/// Writes the contents of `buf`. `offset` of `None` writes at the current
+/// file position.
+pub async fn write(fd: RawFd, buf: Vec<u8>, offset: Option<u64>) -> (io::Result<usize>, Vec<u8>) {
+    let sqe = Sqe {
+        opcode: IORING_OP_WRITE,
+        fd,
+        addr: buf.as_ptr() as u64,
+        len: buf.len().min(u32::MAX as usize) as u32,
+        off: offset.unwrap_or(u64::MAX),
+        ..Sqe::default()
+    };
+    match Op::submit(sqe, buf, false) {
+        Ok(op) => {
+            let (res, buf) = op.await;
+            (res.map(|n| n as usize), buf)
+        }
+        Err((e, buf)) => (Err(e), buf),
+    }
+}
+
+/// Accepts a connection on a listening socket. The socket may be blocking.
+pub async fn accept(fd: RawFd) -> io::Result<OwnedFd> {
+    let sqe = Sqe {
+        opcode: IORING_OP_ACCEPT,
+        fd,
+        op_flags: SOCK_CLOEXEC,
+        ..Sqe::default()
+    };
+    let op = Op::submit(sqe, (), true).map_err(|(e, _)| e)?;
+    let (res, ()) = op.await;
+    Ok(unsafe { OwnedFd::from_raw_fd(res? as RawFd) })
+}
+
+pub async fn connect(fd: RawFd, addr: SocketAddr) -> io::Result<()> {
+    let raw = sockaddr_bytes(&addr);
+    let sqe = Sqe {
+        opcode: IORING_OP_CONNECT,
+        fd,
+        addr: raw.as_ptr() as u64,
+        off: raw.len() as u64,
+        ..Sqe::default()
+    };
+    let op = Op::submit(sqe, raw, false).map_err(|(e, _)| e)?;
+    op.await.0.map(|_| ())
+}
+
+/// Opens `path` relative to `dirfd` (`AT_FDCWD` for the working directory).
+/// `O_CLOEXEC` is always added to `flags`.
+pub async fn openat(
+    dirfd: RawFd,
+    path: impl AsRef<Path>,
+    flags: i32,
+    mode: u32,
+) -> io::Result<OwnedFd> {
+    let path = CString::new(path.as_ref().as_os_str().as_bytes())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "path contains a nul byte"))?;
+    let sqe = Sqe {
+        opcode: IORING_OP_OPENAT,
+        fd: dirfd,
+        addr: path.as_ptr() as u64,
+        len: mode,
+        op_flags: (flags | O_CLOEXEC) as u32,
+        ..Sqe::default()
+    };
+    let op = Op::submit(sqe, path, true).map_err(|(e, _)| e)?;
+    let (res, _) = op.await;
+    Ok(unsafe { OwnedFd::from_raw_fd(res? as RawFd) })
+}
+
+pub async fn fsync(fd: RawFd) -> io::Result<()> {
+    let sqe = Sqe {
+        opcode: IORING_OP_FSYNC,
+        fd,
+        ..Sqe::default()
+    };
+    let op = Op::submit(sqe, (), false).map_err(|(e, _)| e)?;
+    op.await.0.map(|_| ())
+}
+
+fn sockaddr_bytes(addr: &SocketAddr) -> Vec<u8> {
+    let mut raw = Vec::with_capacity(28);
+    match addr {
+        SocketAddr::V4(a) => {
+            raw.extend_from_slice(&AF_INET.to_ne_bytes());
+            raw.extend_from_slice(&a.port().to_be_bytes());
+            raw.extend_from_slice(&a.ip().octets());
+            raw.extend_from_slice(&[0u8; 8]);
+        }
+        SocketAddr::V6(a) => {
+            raw.extend_from_slice(&AF_INET6.to_ne_bytes());
+            raw.extend_from_slice(&a.port().to_be_bytes());
+            raw.extend_from_slice(&a.flowinfo().to_be_bytes());
+            raw.extend_from_slice(&a.ip().octets());
+            raw.extend_from_slice(&a.scope_id().to_ne_bytes());
+        }
+    }
+    raw
+}
+
+enum State {
+    Pending(Option<Waker>),
+    Done(i32),
+    // The future was dropped; the entry only keeps `data` alive until the
+    // kernel posts the completion.
+    Abandoned,
+}
+
+struct InFlight {
+    state: State,
+    data: Box<dyn Any + Send>,
+    // The result is a new fd that nobody will own if the op was abandoned.
+    returns_fd: bool,
+}
+
+struct Driver {
+    ring: Ring,
+    ops: HashMap<u64, InFlight>,
+    next_key: u64,
+}
+
+impl Driver {
+    fn push<T: Send + 'static>(
+        &mut self,
+        mut sqe: Sqe,
+        data: T,
+        returns_fd: bool,
+    ) -> Result<u64, (io::Error, T)> {
+        let key = self.next_key;
+        self.next_key += 1;
+        sqe.user_data = key;
+        if let Err(e) = self.ring.push(sqe) {
+            return Err((e, data));
+        }
+        self.ops.insert(
+            key,
+            InFlight {
+                state: State::Pending(None),
+                data: Box::new(data),
+                returns_fd,
+            },
+        );
+        // A failed submit leaves the entry queued; it goes out with the next
+        // submit after a completion frees up the kernel.
+        let _ = self.ring.submit();
+        Ok(key)
+    }
+
+    fn cancel(&mut self, key: u64) {
+        let sqe = Sqe {
+            opcode: IORING_OP_ASYNC_CANCEL,
+            addr: key,
+            user_data: CANCEL_USER_DATA,
+            ..Sqe::default()
+        };
+        if self.ring.push(sqe).is_ok() {
+            let _ = self.ring.submit();
+        }
+    }
+
+    fn reap(&mut self) -> Vec<Waker> {
+        let mut wakers = Vec::new();
+        while let Some(cqe) = self.ring.pop_cqe() {
+            if cqe.user_data == CANCEL_USER_DATA {
+                continue;
+            }
+            let Some(op) = self.ops.get_mut(&cqe.user_data) else {
+                continue;
+            };
+            match std::mem::replace(&mut op.state, State::Done(cqe.res)) {
+                State::Pending(waker) => wakers.extend(waker),
+                State::Abandoned => {
+                    if op.returns_fd && cqe.res >= 0 {
+                        unsafe {
+                            let _ = close(cqe.res);
+                        }
+                    }
+                    self.ops.remove(&cqe.user_data);
+                }
+                State::Done(_) => {}
+            }
+        }
+        let _ = self.ring.submit();
+        wakers
+    }
+}
+
+impl Drop for Driver {
+    fn drop(&mut self) {
+        // Closing the ring does not wait for in-flight operations, which may
+        // still write into buffers held here.
+        let keys: Vec<u64> = self.ops.keys().copied().collect();
+        for key in keys {
+            self.cancel(key);
+        }
+        let deadline = Instant::now() + DRAIN_TIMEOUT;
+        while !self.ops.is_empty() {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() || self.ring.submit_and_wait(Some(left)).is_err() {
+                break;
+            }
+            self.reap();
+        }
+        // Operations that did not finish in time may still write into their
+        // buffers after the ring is closed, so those buffers are leaked.
+        for (_, op) in self.ops.drain() {
+            std::mem::forget(op.data);
+        }
+    }
+}
+
+thread_local! {
+    // Keyed by loop id, since several loops may take turns on one thread.
+    static DRIVERS: RefCell<HashMap<u64, Weak<Mutex<Driver>>>> = RefCell::new(HashMap::new());
+}
+
+fn current_driver() -> io::Result<Arc<Mutex<Driver>>> {
+    with_current_loop(|loop_ref| {
+        let id = loop_ref.id();
+        let existing = DRIVERS.with(|d| d.borrow().get(&id).and_then(Weak::upgrade));
+        if let Some(driver) = existing {
+            return Ok(driver);
+        }
+
+        let ring = Ring::new(256)?;
+        let fd = ring.fd();
+        let driver = Arc::new(Mutex::new(Driver {
+            ring,
+            ops: HashMap::new(),
+            next_key: 0,
+        }));
+        // The loop's callback holds the only long-lived reference, so the
+        // ring goes away with the loop.
+        let cb_driver = driver.clone();
+        loop_ref.add_io(fd, Interest::Readable, move |_, _| {
+            let wakers = lock(&cb_driver).reap();
+            for w in wakers {
+                w.wake();
+            }
+        })?;
+        DRIVERS.with(|d| {
+            let mut drivers = d.borrow_mut();
+            drivers.retain(|_, weak| weak.strong_count() > 0);
+            drivers.insert(id, Arc::downgrade(&driver));
+        });
+        Ok(driver)
+    })
+}
+
+struct Op<T> {
+    driver: Arc<Mutex<Driver>>,
+    key: u64,
+    done: bool,
+    _data: PhantomData<fn() -> T>,
+}
+
+impl<T: Send + 'static> Op<T> {
+    // `data` must own every buffer `sqe` points into.
+    fn submit(sqe: Sqe, data: T, returns_fd: bool) -> Result<Self, (io::Error, T)> {
+        let driver = match current_driver() {
+            Ok(d) => d,
+            Err(e) => return Err((e, data)),
+        };
+        let key = lock(&driver).push(sqe, data, returns_fd)?;
+        Ok(Self {
+            driver,
+            key,
+            done: false,
+            _data: PhantomData,
+        })
+    }
+}
+
+impl<T: 'static> Future for Op<T> {
+    type Output = (io::Result<u32>, T);
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let key = this.key;
+        let mut driver = lock(&this.driver);
+        let op = driver.ops.get_mut(&key).expect("uring op entry missing");
+        let res = match &mut op.state {
+            State::Done(res) => *res,
+            State::Pending(waker) => {
+                *waker = Some(cx.waker().clone());
+                return Poll::Pending;
+            }
+            State::Abandoned => unreachable!("uring op polled after drop"),
+        };
+        let op = driver.ops.remove(&key).unwrap();
+        drop(driver);
+        this.done = true;
+
+        let data = *op
+            .data
+            .downcast::<T>()
+            .expect("uring op data type mismatch");
+        let res = if res < 0 {
+            Err(io::Error::from_raw_os_error(-res))
+        } else {
+            Ok(res as u32)
+        };
+        Poll::Ready((res, data))
+    }
+}
+
+impl<T> Drop for Op<T> {
+    fn drop(&mut self) {
+        if self.done {
+            return;
+        }
+        let mut driver = lock(&self.driver);
+        let Some(op) = driver.ops.get_mut(&self.key) else {
+            return;
+        };
+        if let State::Done(res) = op.state {
+            let op = driver.ops.remove(&self.key).unwrap();
+            if op.returns_fd && res >= 0 {
+                unsafe {
+                    let _ = close(res);
+                }
+            }
+            return;
+        }
+        op.state = State::Abandoned;
+        driver.cancel(self.key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::async_rt::time::timeout;
+    use crate::BackendKind;
+    use std::io::Read;
+    use std::os::unix::io::AsRawFd;
+    use std::os::unix::net::UnixStream;
+
+    fn unsupported<T>(res: &io::Result<T>) -> bool {
+        matches!(res, Err(e) if e.kind() == io::ErrorKind::Unsupported)
+    }
+
+    #[test]
+    fn write_then_read_at_offsets() {
+        crate::run(BackendKind::Epoll, async {
+            let path = std::env::temp_dir().join(format!("uring-test-{}", std::process::id()));
+            let file = std::fs::File::options()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(&path)
+                .unwrap();
+            std::fs::remove_file(&path).unwrap();
+            let fd = file.as_raw_fd();
+
+            let (res, _) = write(fd, b"hello world".to_vec(), Some(0)).await;
+            if unsupported(&res) {
+                return;
+            }
+            assert_eq!(res.unwrap(), 11);
+            fsync(fd).await.unwrap();
+
+            let (res, buf) = read(fd, Vec::with_capacity(5), Some(6)).await;
+            assert_eq!(res.unwrap(), 5);
+            assert_eq!(buf, b"world");
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn dropped_read_is_cancelled() {
+        crate::run(BackendKind::Epoll, async {
+            let (mut a, b) = UnixStream::pair().unwrap();
+            let fd = b.as_raw_fd();
+
+            let res = timeout(
+                Duration::from_millis(20),
+                read(fd, Vec::with_capacity(4), None),
+            )
+            .await;
+            match res {
+                Ok((res, _)) if unsupported(&res) => return,
+                Ok(_) => panic!("read completed without data"),
+                Err(_) => {}
+            }
+
+            // Give the loop a turn to submit the cancellation; the cancelled
+            // read must not consume the data.
+            crate::async_rt::time::sleep(Duration::from_millis(10)).await;
+            std::io::Write::write_all(&mut a, b"ping").unwrap();
+            let mut buf = [0; 4];
+            (&b).read_exact(&mut buf).unwrap();
+            assert_eq!(&buf, b"ping");
+        })
+        .unwrap();
+    }
+}
@@ -2,7 +2,12 @@ pub mod async_rt;
 pub mod runtime;
 
 pub use eventloop_async_research_macros::main;
-pub use runtime::{BackendKind, EventLoop, Handle, Interest, IoWatcher, Ready};
+pub use runtime::{
+    new_poller, Activity, BackendKind, CallFuture, EventLoop, Handle, Histogram, HookId, Interest,
+    IoMode, IoWatcher, JsonLinesWriter, LoopMetrics, LoopObserver, MissedTickBehavior, Poller,
+    PollerWaker, Ready, RemoteTimerHandle, SignalId, SignalKind, Stall, TextLogger, TimerHandle,
+    TimerId, TimerKind, Token, Watchdog,
+};
 
 pub fn default_backend() -> BackendKind {
     if cfg!(target_os = "linux") {
@@ -16,6 +21,7 @@ pub fn backend_from_args() -> BackendKind {
     match std::env::args().nth(1).as_deref() {
         Some("poll") => BackendKind::Poll,
         Some("epoll") => BackendKind::Epoll,
+        Some("uring") | Some("io_uring") => BackendKind::IoUring,
         _ => default_backend(),
     }
 }
@@ -36,10 +42,10 @@ where
     exec.spawn(async move {
         let result = fut.await;
         *out2.lock().unwrap() = Some(result);
-        let _ = handle.post(|loop_ref| loop_ref.request_exit());
+        let _ = handle.post_unbounded(|loop_ref| loop_ref.request_exit());
     });
 
-    event_loop.run();
+    event_loop.run()?;
     let result = out
         .lock()
         .unwrap()
@@ -1,55 +1,95 @@
-use super::{os, BackendKind, Interest, Ready};
+use super::{os, BackendKind, Interest, IoMode, Ready, Token};
 use std::io;
 use std::os::unix::io::RawFd;
+use std::sync::Arc;
 use std::time::Duration;
 
-pub(crate) enum Backend {
-    #[cfg(target_os = "linux")]
-    Epoll(os::linux::EpollBackend),
-    Poll(os::unix::PollBackend),
-}
+/// I/O demultiplexer driving an `EventLoop`.
+///
+/// The loop calls `register` once per token, `modify` to change the interest
+/// of a registered token (which also re-arms a fired oneshot registration),
+/// and `deregister` when the registration goes away, possibly after the fd
+/// was closed. Tokens are not reused (a slot is retired once its 32-bit
+/// generation runs out), so a poller may key its state by token.
+pub trait Poller {
+    fn register(
+        &mut self,
+        fd: RawFd,
+        token: Token,
+        interest: Interest,
+        mode: IoMode,
+    ) -> io::Result<()>;
 
-impl Backend {
-    pub(crate) fn new(kind: BackendKind) -> io::Result<Self> {
-        match kind {
-            BackendKind::Poll => Ok(Self::Poll(os::unix::PollBackend::new()?)),
-            BackendKind::Epoll => {
-                #[cfg(target_os = "linux")]
-                {
-                    Ok(Self::Epoll(os::linux::EpollBackend::new()?))
-                }
-                #[cfg(not(target_os = "linux"))]
-                {
-                    Err(io::Error::new(
-                        io::ErrorKind::Unsupported,
-                        "epoll backend is only supported on linux",
-                    ))
-                }
-            }
-        }
+    fn modify(
+        &mut self,
+        fd: RawFd,
+        token: Token,
+        interest: Interest,
+        mode: IoMode,
+    ) -> io::Result<()>;
+
+    fn deregister(&mut self, fd: RawFd, token: Token) -> io::Result<()>;
+
+    /// Blocks until a registration is ready or `timeout` passes; `None` waits
+    /// indefinitely. Returning no events is always allowed.
+    fn wait(&mut self, timeout: Option<Duration>) -> io::Result<Vec<(Token, Ready)>>;
+
+    /// Returns a waker that interrupts `wait` from other threads; called once,
+    /// when the loop is created. Waking is a separate `Send + Sync` object
+    /// rather than a trait method because handles on other threads must reach
+    /// it while the loop owns the poller.
+    ///
+    /// A poller whose `wait` already returns for any readable fd can keep the
+    /// default `None`: the loop then creates an eventfd (a pipe off Linux),
+    /// registers it through `register` and writes to it to wake up. Return a
+    /// waker when `wait` blocks on something an fd cannot interrupt, such as a
+    /// simulated poller or a host framework's own wakeup.
+    fn waker(&mut self) -> io::Result<Option<Arc<dyn PollerWaker>>> {
+        Ok(None)
     }
 
-    pub(crate) fn register(&mut self, fd: RawFd, interest: Interest) -> io::Result<()> {
-        match self {
-            #[cfg(target_os = "linux")]
-            Backend::Epoll(b) => b.register(fd, interest),
-            Backend::Poll(b) => b.register(fd, interest),
-        }
+    /// Returns an fd that turns readable while `wait` would report events, so
+    /// the loop can be embedded in a host event loop. `None` if the poller has
+    /// no such fd.
+    fn as_raw_fd(&self) -> Option<RawFd> {
+        None
     }
+}
 
-    pub(crate) fn deregister(&mut self, fd: RawFd) -> io::Result<()> {
-        match self {
+/// Cross-thread wakeup supplied by a `Poller`.
+pub trait PollerWaker: Send + Sync {
+    fn wake(&self) -> io::Result<()>;
+}
+
+/// Creates one of the built-in pollers, e.g. to wrap it in another `Poller`.
+pub fn new_poller(kind: BackendKind) -> io::Result<Box<dyn Poller>> {
+    match kind {
+        BackendKind::Poll => Ok(Box::new(os::unix::PollBackend::new()?)),
+        BackendKind::Epoll => {
             #[cfg(target_os = "linux")]
-            Backend::Epoll(b) => b.deregister(fd),
-            Backend::Poll(b) => b.deregister(fd),
+            {
+                Ok(Box::new(os::linux::EpollBackend::new()?))
+            }
+            #[cfg(not(target_os = "linux"))]
+            {
+                Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "epoll backend is only supported on linux",
+                ))
+            }
         }
-    }
-
-    pub(crate) fn wait(&mut self, timeout: Option<Duration>) -> io::Result<Vec<(RawFd, Ready)>> {
-        match self {
+        BackendKind::IoUring => {
             #[cfg(target_os = "linux")]
-            Backend::Epoll(b) => b.wait(timeout),
-            Backend::Poll(b) => b.wait(timeout),
+            {
+                Ok(Box::new(os::uring::IoUringBackend::new()?))
+            }
+            #[cfg(not(target_os = "linux"))]
+            {
+                Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "io_uring backend is only supported on linux",
+                ))
+            }
         }
     }
 }
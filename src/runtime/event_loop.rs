@@ -1,80 +1,150 @@
-use super::backend::Backend;
-use super::timer::Timer;
-use super::waker::make_waker;
-use super::{Handle, Interest, IoWatcher, Ready, Task};
+use super::backend::new_poller;
+use super::hook::{HookId, HookKind, Hooks};
+use super::inject;
+use super::metrics::{LoopMetrics, SharedCounters};
+use super::observer::{JsonLinesWriter, LoopObserver, TextLogger};
+use super::signal::{SignalHandler, SignalId, SignalSource, Signals};
+use super::slab::Slab;
+use super::timer::{RemoteTimers, TimerHandle, TimerId, TimerQueue, TimerTask};
+use super::waker::{make_waker, poller_waker};
+use super::watchdog::{ActiveWatchdog, Activity, Stall, Watchdog};
+use super::{
+    BackendKind, Handle, Interest, IoMode, IoWatcher, MissedTickBehavior, Poller, Ready, Task,
+    TimerKind, Token,
+};
 
-use std::cmp::Reverse;
-use std::collections::{BinaryHeap, HashMap, VecDeque};
+use std::any::Any;
+use std::collections::{HashMap, VecDeque};
 use std::io::{self, Read};
 use std::os::unix::io::{AsRawFd, RawFd};
-use std::sync::mpsc;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
 use std::time::{Duration, Instant};
 
+static NEXT_LOOP_ID: AtomicU64 = AtomicU64::new(0);
+
+type IoCallback = Box<dyn FnMut(&mut super::EventLoop, Ready) + 'static>;
+type IoErrorCallback = Box<dyn FnOnce(&mut super::EventLoop, io::Error) + 'static>;
+type PanicHook = Box<dyn FnMut(&mut super::EventLoop, Box<dyn Any + Send>) + 'static>;
+
 struct Source {
+    fd: RawFd,
     interest: Interest,
-    callback: Box<dyn FnMut(&mut super::EventLoop, Ready) + 'static>,
+    mode: IoMode,
+    callback: Option<IoCallback>,
+    on_error: Option<IoErrorCallback>,
 }
 
 pub struct EventLoop {
-    backend: Backend,
+    id: u64,
+    backend: Box<dyn Poller>,
 
     handle: Handle,
     exit_requested: bool,
     in_dispatch: bool,
-    trace: bool,
+    iteration: u64,
+    observer: Option<Box<dyn LoopObserver>>,
 
-    sources: HashMap<RawFd, Source>,
-    pending_add: Vec<(RawFd, Source)>,
-    pending_remove: Vec<RawFd>,
+    sources: Slab<Source>,
+    fd_tokens: HashMap<RawFd, Token>,
+    pending_add: Vec<Token>,
+    pending_remove: Vec<Token>,
 
     local_tasks: VecDeque<Task>,
-    shared_rx: mpsc::Receiver<Task>,
+    shared_rx: inject::Receiver,
 
-    timers: BinaryHeap<Reverse<Timer>>,
-    timer_seq: u64,
+    timers: TimerQueue,
+
+    signals: Option<Signals>,
+    hooks: Hooks,
+    panic_hook: Option<PanicHook>,
+    metrics: LoopMetrics,
+    watchdog: Option<ActiveWatchdog>,
 }
 
 impl EventLoop {
-    pub fn new(kind: super::BackendKind) -> io::Result<(Self, Handle)> {
-        let backend = Backend::new(kind)?;
-        let (tx, rx) = mpsc::channel::<Task>();
+    pub fn new(kind: BackendKind) -> io::Result<(Self, Handle)> {
+        Self::with_timers(kind, TimerKind::Heap)
+    }
+
+    pub fn with_timers(kind: BackendKind, timers: TimerKind) -> io::Result<(Self, Handle)> {
+        Self::with_backend_and_timers(new_poller(kind)?, timers)
+    }
+
+    /// Runs the loop on a caller-supplied `Poller`.
+    pub fn with_backend(poller: Box<dyn Poller>) -> io::Result<(Self, Handle)> {
+        Self::with_backend_and_timers(poller, TimerKind::Heap)
+    }
 
-        let (mut reader, waker) = make_waker()?;
-        let handle = Handle { tx, waker };
+    pub fn with_backend_and_timers(
+        mut backend: Box<dyn Poller>,
+        timers: TimerKind,
+    ) -> io::Result<(Self, Handle)> {
+        let (tx, rx) = inject::channel();
+
+        let (reader, waker) = match backend.waker()? {
+            Some(w) => (None, poller_waker(w)),
+            None => {
+                let (reader, waker) = make_waker()?;
+                (Some(reader), waker)
+            }
+        };
+        let handle = Handle {
+            tx,
+            waker,
+            counters: Arc::new(SharedCounters::default()),
+            timers: Arc::new(RemoteTimers::new()),
+            loop_thread: std::thread::current().id(),
+        };
 
         let mut loop_ref = Self {
+            id: NEXT_LOOP_ID.fetch_add(1, Ordering::Relaxed),
             backend,
             handle: handle.clone(),
             exit_requested: false,
             in_dispatch: false,
-            trace: std::env::var_os("EVLOOP_TRACE").is_some(),
-            sources: HashMap::new(),
+            iteration: 0,
+            observer: trace_observer(),
+            sources: Slab::new(),
+            fd_tokens: HashMap::new(),
             pending_add: Vec::new(),
             pending_remove: Vec::new(),
             local_tasks: VecDeque::new(),
             shared_rx: rx,
-            timers: BinaryHeap::new(),
-            timer_seq: 0,
+            timers: TimerQueue::new(timers),
+            signals: None,
+            hooks: Hooks::new(),
+            panic_hook: None,
+            metrics: LoopMetrics::new(),
+            watchdog: None,
         };
 
-        loop_ref.add_io(reader.as_raw_fd(), Interest::Readable, move |_, ready| {
-            if !ready.readable {
-                return;
-            }
-            let mut buf = [0u8; 128];
-            loop {
-                match reader.read(&mut buf) {
-                    Ok(0) => return,
-                    Ok(_) => continue,
-                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => return,
-                    Err(_) => return,
+        if let Some(reader) = reader {
+            loop_ref.add_io(reader.as_raw_fd(), Interest::Readable, move |_, ready| {
+                if !ready.readable {
+                    return;
                 }
-            }
-        })?;
+                let mut buf = [0u8; 128];
+                loop {
+                    match (&*reader).read(&mut buf) {
+                        Ok(0) => return,
+                        Ok(_) => continue,
+                        Err(e) if e.kind() == io::ErrorKind::WouldBlock => return,
+                        Err(_) => return,
+                    }
+                }
+            })?;
+        }
 
         Ok((loop_ref, handle))
     }
 
+    /// Unique per loop for the life of the process, unlike its address.
+    pub(crate) fn id(&self) -> u64 {
+        self.id
+    }
+
     pub fn handle(&self) -> Handle {
         self.handle.clone()
     }
@@ -88,39 +158,105 @@ impl EventLoop {
         F: FnOnce(&mut super::EventLoop) + Send + 'static,
     {
         self.local_tasks.push_back(Box::new(f));
+        self.observe(|o| o.task_posted(false));
+    }
+
+    pub fn post_delayed<F>(&mut self, delay: Duration, f: F) -> TimerHandle
+    where
+        F: FnOnce(&mut super::EventLoop) + Send + 'static,
+    {
+        self.post_at(Instant::now() + delay, f)
     }
 
-    pub fn post_delayed<F>(&mut self, delay: Duration, f: F)
+    pub fn post_at<F>(&mut self, when: Instant, f: F) -> TimerHandle
     where
         F: FnOnce(&mut super::EventLoop) + Send + 'static,
     {
-        let when = Instant::now() + delay;
-        let seq = self.timer_seq;
-        self.timer_seq = self.timer_seq.wrapping_add(1);
-        self.timers.push(Reverse(Timer {
-            when,
-            seq,
-            task: Box::new(f),
-        }));
+        let (id, cancelled) = self.timers.insert(when, TimerTask::Once(Box::new(f)));
+        TimerHandle::new(id, cancelled, self.handle())
+    }
+
+    /// Runs `f` every `period`, first one period from now, until cancelled.
+    /// Missed ticks are fired back to back; see `post_repeating_with`.
+    pub fn post_repeating<F>(&mut self, period: Duration, f: F) -> TimerHandle
+    where
+        F: FnMut(&mut super::EventLoop) + Send + 'static,
+    {
+        self.post_repeating_with(period, MissedTickBehavior::Burst, f)
+    }
+
+    pub fn post_repeating_with<F>(
+        &mut self,
+        period: Duration,
+        behavior: MissedTickBehavior,
+        f: F,
+    ) -> TimerHandle
+    where
+        F: FnMut(&mut super::EventLoop) + Send + 'static,
+    {
+        assert!(!period.is_zero(), "post_repeating: period must be non-zero");
+        let when = Instant::now() + period;
+        let task = TimerTask::Repeating {
+            period,
+            behavior,
+            f: Box::new(f),
+        };
+        let (id, cancelled) = self.timers.insert(when, task);
+        TimerHandle::new(id, cancelled, self.handle())
+    }
+
+    /// Cancels a pending timer. Returns `false` if it already fired or was
+    /// cancelled.
+    pub fn cancel_timer(&mut self, id: TimerId) -> bool {
+        self.timers.cancel(id)
+    }
+
+    /// Moves a pending timer to a new deadline. Returns `false` if it already
+    /// fired or was cancelled.
+    pub fn reset_timer(&mut self, id: TimerId, when: Instant) -> bool {
+        self.timers.reset(id, when)
+    }
+
+    /// Registers `fd` and returns the token identifying this registration.
+    ///
+    /// An fd has at most one registration. Registering an fd again replaces
+    /// the existing registration instead of failing with `AlreadyExists`:
+    /// the old callback is dropped and its token goes stale. A closed fd's
+    /// number can be reused while the `remove_io` its `IoWatcher` queued when
+    /// dropped outside the loop is still pending; that stale removal is then
+    /// ignored.
+    pub fn add_io<F>(&mut self, fd: RawFd, interest: Interest, callback: F) -> io::Result<Token>
+    where
+        F: FnMut(&mut super::EventLoop, Ready) + 'static,
+    {
+        self.add_io_with_mode(fd, interest, IoMode::LEVEL, callback)
     }
 
-    pub fn add_io<F>(&mut self, fd: RawFd, interest: Interest, callback: F) -> io::Result<()>
+    pub fn add_io_with_mode<F>(
+        &mut self,
+        fd: RawFd,
+        interest: Interest,
+        mode: IoMode,
+        callback: F,
+    ) -> io::Result<Token>
     where
         F: FnMut(&mut super::EventLoop, Ready) + 'static,
     {
-        let src = Source {
+        let token = self.sources.insert(Source {
+            fd,
             interest,
-            callback: Box::new(callback),
-        };
+            mode,
+            callback: Some(Box::new(callback)),
+            on_error: None,
+        });
 
         if self.in_dispatch {
-            self.pending_add.push((fd, src));
-            return Ok(());
+            self.pending_add.push(token);
+            return Ok(token);
         }
 
-        self.backend.register(fd, interest)?;
-        self.sources.insert(fd, src);
-        Ok(())
+        self.register_source(token)?;
+        Ok(token)
     }
 
     pub fn watch_io<F>(
@@ -132,84 +268,588 @@ impl EventLoop {
     where
         F: FnMut(&mut super::EventLoop, Ready) + 'static,
     {
-        self.add_io(fd, interest, callback)?;
-        Ok(IoWatcher::new(fd, self.handle()))
+        self.watch_io_with_mode(fd, interest, IoMode::LEVEL, callback)
     }
 
-    pub fn remove_io(&mut self, fd: RawFd) -> io::Result<()> {
-        if self.in_dispatch {
-            self.pending_remove.push(fd);
+    pub fn watch_io_with_mode<F>(
+        &mut self,
+        fd: RawFd,
+        interest: Interest,
+        mode: IoMode,
+        callback: F,
+    ) -> io::Result<IoWatcher>
+    where
+        F: FnMut(&mut super::EventLoop, Ready) + 'static,
+    {
+        let token = self.add_io_with_mode(fd, interest, mode, callback)?;
+        Ok(IoWatcher::new(token, self.handle(), self.id))
+    }
+
+    /// Changes the interest of a live registration, keeping its `IoMode`.
+    ///
+    /// For `IoMode::oneshot` registrations this also re-arms the source.
+    pub fn modify_io(&mut self, token: Token, interest: Interest) -> io::Result<()> {
+        let Some(src) = self.sources.get_mut(token) else {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "io registration not found",
+            ));
+        };
+        src.interest = interest;
+        let (fd, mode) = (src.fd, src.mode);
+        if self.pending_add.contains(&token) {
             return Ok(());
         }
-        self.backend.deregister(fd)?;
-        self.sources.remove(&fd);
+        self.backend.modify(fd, token, interest, mode)
+    }
+
+    /// Runs `callback` instead of failing `run_once` if handing `token` to the
+    /// backend fails. Registrations made from inside an I/O callback are
+    /// deferred until dispatch finishes, so `add_io` cannot report those
+    /// errors itself.
+    pub fn set_io_error_callback<F>(&mut self, token: Token, callback: F) -> io::Result<()>
+    where
+        F: FnOnce(&mut super::EventLoop, io::Error) + 'static,
+    {
+        let Some(src) = self.sources.get_mut(token) else {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "io registration not found",
+            ));
+        };
+        src.on_error = Some(Box::new(callback));
         Ok(())
     }
 
-    pub fn run(&mut self) {
-        while !self.exit_requested {
-            self.drain_shared_tasks();
-            self.run_expired_timers();
-            self.run_local_tasks();
-            if self.exit_requested {
-                break;
+    /// Removes the registration identified by `token`. Stale tokens are ignored.
+    pub fn remove_io(&mut self, token: Token) -> io::Result<()> {
+        if self.in_dispatch {
+            self.pending_remove.push(token);
+            return Ok(());
+        }
+        let Some(src) = self.sources.remove(token) else {
+            return Ok(());
+        };
+        if self.fd_tokens.get(&src.fd) != Some(&token) {
+            return Ok(());
+        }
+        self.fd_tokens.remove(&src.fd);
+        self.backend.deregister(src.fd, token)
+    }
+
+    /// Runs `callback` on the loop each time `signo` is delivered; several
+    /// handlers may watch the same signal.
+    ///
+    /// On Linux the signal is blocked on the calling thread, which must be the
+    /// loop thread. Other threads should block it too, or the kernel may
+    /// deliver it to one of them; threads spawned afterwards inherit the mask.
+    pub fn add_signal<F>(&mut self, signo: i32, callback: F) -> io::Result<SignalId>
+    where
+        F: FnMut(&mut super::EventLoop) + 'static,
+    {
+        if self.signals.is_none() {
+            let source = SignalSource::new()?;
+            self.add_io(source.fd(), Interest::Readable, |loop_ref, _| {
+                loop_ref.dispatch_signals();
+            })?;
+            self.signals = Some(Signals {
+                source,
+                handlers: Slab::new(),
+                by_signo: HashMap::new(),
+            });
+        }
+        let signals = self.signals.as_mut().unwrap();
+
+        if !signals.by_signo.contains_key(&signo) {
+            signals.source.add(signo)?;
+        }
+        let token = signals.handlers.insert(SignalHandler {
+            signo,
+            callback: Some(Box::new(callback)),
+        });
+        signals.by_signo.entry(signo).or_default().push(token);
+        Ok(SignalId(token))
+    }
+
+    /// Removes a signal handler. Once no handler watches the signal, it is
+    /// blocked or unblocked again as it was before the first `add_signal`.
+    pub fn remove_signal(&mut self, id: SignalId) -> bool {
+        let Some(signals) = self.signals.as_mut() else {
+            return false;
+        };
+        let Some(handler) = signals.handlers.remove(id.0) else {
+            return false;
+        };
+        if let Some(ids) = signals.by_signo.get_mut(&handler.signo) {
+            ids.retain(|&t| t != id.0);
+            if ids.is_empty() {
+                signals.by_signo.remove(&handler.signo);
+                let _ = signals.source.remove(handler.signo);
             }
+        }
+        true
+    }
 
-            let timeout = self.compute_timeout();
-            let events = match self.backend.wait(timeout) {
-                Ok(ev) => ev,
-                Err(e) => {
-                    eprintln!("backend wait error: {e}");
-                    break;
+    fn dispatch_signals(&mut self) {
+        let Some(signals) = self.signals.as_mut() else {
+            return;
+        };
+        for signo in signals.source.read() {
+            let ids = match self.signals.as_ref().and_then(|s| s.by_signo.get(&signo)) {
+                Some(ids) => ids.clone(),
+                None => continue,
+            };
+            for id in ids {
+                let Some(mut cb) = self.signal_handler(id).and_then(|h| h.callback.take()) else {
+                    continue;
+                };
+                self.run_callback(|| Activity::Signal(signo), |lp| cb(lp));
+                // The handler may have removed itself.
+                if let Some(h) = self.signal_handler(id) {
+                    h.callback = Some(cb);
                 }
+            }
+        }
+    }
+
+    fn signal_handler(&mut self, id: Token) -> Option<&mut SignalHandler> {
+        self.signals.as_mut()?.handlers.get_mut(id)
+    }
+
+    /// Runs `callback` on every iteration right before the loop blocks in the
+    /// backend.
+    pub fn add_prepare<F>(&mut self, callback: F) -> HookId
+    where
+        F: FnMut(&mut super::EventLoop) + 'static,
+    {
+        self.hooks.insert(HookKind::Prepare, Box::new(callback))
+    }
+
+    /// Runs `callback` on every iteration right after I/O callbacks were
+    /// dispatched.
+    pub fn add_check<F>(&mut self, callback: F) -> HookId
+    where
+        F: FnMut(&mut super::EventLoop) + 'static,
+    {
+        self.hooks.insert(HookKind::Check, Box::new(callback))
+    }
+
+    /// Runs `callback` on every iteration before the prepare hooks. While any
+    /// idle hook is registered the loop polls without blocking.
+    pub fn add_idle<F>(&mut self, callback: F) -> HookId
+    where
+        F: FnMut(&mut super::EventLoop) + 'static,
+    {
+        self.hooks.insert(HookKind::Idle, Box::new(callback))
+    }
+
+    pub fn remove_hook(&mut self, id: HookId) -> bool {
+        self.hooks.remove(id)
+    }
+
+    fn run_hooks(&mut self, kind: HookKind) {
+        for id in self.hooks.ids(kind) {
+            let Some(mut callback) = self.hooks.take(id) else {
+                continue;
             };
-            if self.trace {
-                eprintln!("wait -> {} events: {:?}", events.len(), events);
+            self.run_callback(|| Activity::Hook, |lp| callback(lp));
+            self.hooks.put_back(id, callback);
+        }
+    }
+
+    /// Runs `hook` with the payload whenever an I/O callback, task, timer,
+    /// hook or signal handler panics; the loop then carries on. The panicking
+    /// task or one-shot timer is gone, while I/O registrations, repeating
+    /// timers and hooks stay installed. Without a hook the payload is dropped
+    /// after the process panic hook printed it; call
+    /// `std::panic::resume_unwind` from `hook` to let panics escape `run`.
+    ///
+    /// Panics in `async_rt` tasks are delivered to their `JoinHandle` instead.
+    pub fn on_panic<F>(&mut self, hook: F)
+    where
+        F: FnMut(&mut super::EventLoop, Box<dyn Any + Send>) + 'static,
+    {
+        self.panic_hook = Some(Box::new(hook));
+    }
+
+    /// Starts a watchdog thread that calls `hook` whenever an I/O callback,
+    /// timer, task, hook or `Executor` task poll runs longer than
+    /// `threshold`, once per stall. `hook` runs on the watchdog thread, as the
+    /// loop thread is the one stuck. Replaces a previous watchdog.
+    ///
+    /// The returned `Watchdog` also reports the loop's heartbeat age.
+    pub fn enable_watchdog<F>(&mut self, threshold: Duration, hook: F) -> io::Result<Watchdog>
+    where
+        F: FnMut(&Stall) + Send + 'static,
+    {
+        self.watchdog = None;
+        let (active, watchdog) = ActiveWatchdog::start(threshold, Box::new(hook))?;
+        self.watchdog = Some(active);
+        Ok(watchdog)
+    }
+
+    /// Stops the watchdog thread, if any.
+    pub fn disable_watchdog(&mut self) {
+        self.watchdog = None;
+    }
+
+    /// Runs `f` as `activity` for the watchdog, if one is enabled.
+    pub(crate) fn track<A, F, R>(&mut self, activity: A, f: F) -> R
+    where
+        A: FnOnce() -> Activity,
+        F: FnOnce(&mut super::EventLoop) -> R,
+    {
+        let Some(watchdog) = self.watchdog.as_ref() else {
+            return f(self);
+        };
+        let prev = watchdog.enter(activity());
+        let out = f(self);
+        // `f` may have replaced or disabled the watchdog.
+        if let Some(watchdog) = self.watchdog.as_ref() {
+            watchdog.exit(prev);
+        }
+        out
+    }
+
+    /// Runs a user callback, tracked by the watchdog and with panics caught.
+    fn run_callback<A, F>(&mut self, activity: A, f: F)
+    where
+        A: FnOnce() -> Activity,
+        F: FnOnce(&mut super::EventLoop),
+    {
+        let result = self.track(activity, |lp| {
+            panic::catch_unwind(AssertUnwindSafe(|| f(lp)))
+        });
+        let Err(payload) = result else {
+            return;
+        };
+        let Some(hook) = self.panic_hook.take() else {
+            return;
+        };
+        // Put back even if the hook unwinds, e.g. through `resume_unwind`.
+        let mut guard = RestoreHook {
+            lp: self,
+            hook: Some(hook),
+        };
+        if let Some(hook) = guard.hook.as_mut() {
+            hook(guard.lp, payload);
+        }
+    }
+
+    /// Installs `observer`, replacing the current one. Setting `EVLOOP_TRACE`
+    /// installs a `TextLogger` on stderr at construction, or a
+    /// `JsonLinesWriter` with `EVLOOP_TRACE=json`.
+    pub fn set_observer<O>(&mut self, observer: O)
+    where
+        O: LoopObserver + 'static,
+    {
+        self.observer = Some(Box::new(observer));
+    }
+
+    pub fn take_observer(&mut self) -> Option<Box<dyn LoopObserver>> {
+        self.observer.take()
+    }
+
+    /// Bounds how many tasks `Handle`s may have queued before the loop picks
+    /// them up; `None`, the default, means unbounded. Once full, `post` and
+    /// `try_post` fail and `post_wait` and `post_async` wait for room.
+    pub fn set_queue_capacity(&mut self, capacity: Option<usize>) {
+        self.shared_rx.set_capacity(capacity);
+    }
+
+    /// Returns a snapshot of the loop's counters and histograms.
+    pub fn metrics(&self) -> LoopMetrics {
+        let counters = &self.handle.counters;
+        let spawned = counters.tasks_spawned.load(Ordering::Relaxed);
+        let completed = counters.tasks_completed.load(Ordering::Relaxed);
+        LoopMetrics {
+            iterations: self.iteration,
+            shared_queue_depth: self.handle.tx.len(),
+            registered_sources: self.sources.len(),
+            tasks_live: spawned.saturating_sub(completed),
+            tasks_completed: completed,
+            ..self.metrics.clone()
+        }
+    }
+
+    pub(crate) fn is_observed(&self) -> bool {
+        self.observer.is_some()
+    }
+
+    pub(crate) fn observe<F>(&mut self, f: F)
+    where
+        F: FnOnce(&mut dyn LoopObserver),
+    {
+        if let Some(observer) = self.observer.as_mut() {
+            f(observer.as_mut());
+        }
+    }
+
+    fn register_source(&mut self, token: Token) -> io::Result<()> {
+        let Some(src) = self.sources.get(token) else {
+            return Ok(());
+        };
+        let (fd, interest, mode) = (src.fd, src.interest, src.mode);
+
+        if let Some(old) = self.fd_tokens.insert(fd, token) {
+            if old != token {
+                self.sources.remove(old);
+                let _ = self.backend.deregister(fd, old);
             }
+        }
 
-            self.in_dispatch = true;
-            for (fd, ready) in events {
-                let Some(mut src) = self.sources.remove(&fd) else {
-                    continue;
-                };
-                (src.callback)(self, ready);
-                let remove_requested = self.pending_remove.iter().any(|&x| x == fd);
-                if !remove_requested && !self.sources.contains_key(&fd) {
-                    self.sources.insert(fd, src);
+        if let Err(e) = self.backend.register(fd, token, interest, mode) {
+            self.sources.remove(token);
+            self.fd_tokens.remove(&fd);
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Runs until `request_exit` is called, or until an iteration fails.
+    pub fn run(&mut self) -> io::Result<()> {
+        while !self.exit_requested {
+            self.iterate(None)?;
+        }
+        self.exit_requested = false;
+        Ok(())
+    }
+
+    /// Runs for at most `duration`, returning early on `request_exit`. A
+    /// `duration` too large to add to the current time runs like `run`.
+    pub fn run_for(&mut self, duration: Duration) -> io::Result<()> {
+        let deadline = Instant::now().checked_add(duration);
+        while !self.exit_requested {
+            let timeout = match deadline {
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        break;
+                    }
+                    Some(deadline - now)
                 }
+                None => None,
+            };
+            self.iterate(timeout)?;
+        }
+        self.exit_requested = false;
+        Ok(())
+    }
+
+    /// Runs until `done` returns true, checking it before every iteration,
+    /// or until `request_exit` is called.
+    pub fn run_until<F>(&mut self, mut done: F) -> io::Result<()>
+    where
+        F: FnMut(&mut super::EventLoop) -> bool,
+    {
+        while !self.exit_requested && !done(self) {
+            self.iterate(None)?;
+        }
+        self.exit_requested = false;
+        Ok(())
+    }
+
+    /// Returns an fd that turns readable when the loop has I/O to dispatch,
+    /// for embedding it in a host event loop: the epoll fd, or with the poll
+    /// backend an epoll fd mirroring its registrations (Linux only). The host
+    /// watches it for readability, sleeps at most `next_timeout`, and then
+    /// calls `run_once(Some(Duration::ZERO))`.
+    ///
+    /// The poll backend builds its mirror on the first call and from then on
+    /// keeps it in sync on every registration change and drains it after
+    /// every wait, roughly doubling its syscalls per iteration; prefer the
+    /// epoll backend when embedding.
+    ///
+    /// `None` for the io_uring backend, which only re-arms level-triggered
+    /// registrations inside `run_once`, and for pollers without such an fd.
+    pub fn as_raw_fd(&self) -> Option<RawFd> {
+        self.backend.as_raw_fd()
+    }
+
+    /// How long a host loop may sleep before the loop has work due: zero
+    /// while tasks are queued or idle hooks are installed, `None` when only
+    /// I/O or a cross-thread post can wake it.
+    pub fn next_timeout(&mut self) -> Option<Duration> {
+        self.take_remote_timers();
+        self.compute_timeout()
+    }
+
+    /// Runs a single iteration: queued tasks, expired timers and hooks, then
+    /// waits for I/O for at most `timeout` (`None` blocks until the next event
+    /// or timer), dispatches it and runs the tasks and timers that became due.
+    /// Returns the number of I/O callbacks run.
+    ///
+    /// Fails if the backend does, or if a registration deferred during
+    /// dispatch could not be made and has no error callback; the iteration
+    /// is still completed first.
+    ///
+    /// A pending `request_exit` makes it return without waiting for I/O and
+    /// is consumed, so the next call runs a full iteration again.
+    pub fn run_once(&mut self, timeout: Option<Duration>) -> io::Result<usize> {
+        let res = self.iterate(timeout);
+        self.exit_requested = false;
+        res
+    }
+
+    /// `run_once` without consuming `request_exit`, for the `run` loops.
+    fn iterate(&mut self, timeout: Option<Duration>) -> io::Result<usize> {
+        self.iteration += 1;
+        let iteration = self.iteration;
+        self.observe(|o| o.iteration_start(iteration));
+        self.run_due_work();
+        if self.exit_requested {
+            return Ok(0);
+        }
+
+        self.run_hooks(HookKind::Idle);
+        self.run_hooks(HookKind::Prepare);
+        if self.exit_requested {
+            return Ok(0);
+        }
+
+        let timeout = loop {
+            let t = match (self.compute_timeout(), timeout) {
+                (Some(a), Some(b)) => Some(a.min(b)),
+                (a, b) => a.or(b),
+            };
+            if self.handle.timers.sleep(t) {
+                break t;
             }
-            self.in_dispatch = false;
+            self.take_remote_timers();
+        };
+        self.observe(|o| o.wait_enter(timeout));
+        if let Some(watchdog) = self.watchdog.as_ref() {
+            watchdog.beat(true);
+        }
+        let wait_start = Instant::now();
+        let events = self.backend.wait(timeout);
+        self.handle.timers.awake();
+        if let Some(watchdog) = self.watchdog.as_ref() {
+            watchdog.beat(false);
+        }
+        let events = events?;
+        let (n, waited) = (events.len(), wait_start.elapsed());
+        self.metrics.wait_seconds.observe_duration(waited);
+        self.metrics.events_per_wait.observe(n as f64);
+        self.observe(|o| o.wait_exit(n, waited));
 
-            if !self.pending_remove.is_empty() {
-                let to_remove = std::mem::take(&mut self.pending_remove);
-                for fd in to_remove {
-                    let _ = self.remove_io(fd);
+        let mut dispatched = 0;
+        self.in_dispatch = true;
+        for (token, ready) in events {
+            if self.pending_remove.contains(&token) {
+                continue;
+            }
+            let Some(src) = self.sources.get_mut(token) else {
+                continue;
+            };
+            let fd = src.fd;
+            let Some(mut callback) = src.callback.take() else {
+                continue;
+            };
+            let start = Instant::now();
+            let escaped = panic::catch_unwind(AssertUnwindSafe(|| {
+                self.run_callback(|| Activity::Io { token, fd }, |lp| callback(lp, ready))
+            }));
+            if let Err(payload) = escaped {
+                // The panic hook rethrew: leave the loop usable for another
+                // `run`.
+                if let Some(src) = self.sources.get_mut(token) {
+                    src.callback = Some(callback);
                 }
+                self.in_dispatch = false;
+                panic::resume_unwind(payload);
+            }
+            let took = start.elapsed();
+            self.metrics.callback_seconds.observe_duration(took);
+            self.observe(|o| o.dispatch(token, fd, ready, took));
+            dispatched += 1;
+            if let Some(src) = self.sources.get_mut(token) {
+                src.callback = Some(callback);
             }
+        }
+        self.in_dispatch = false;
 
-            if !self.pending_add.is_empty() {
-                let to_add = std::mem::take(&mut self.pending_add);
-                for (fd, src) in to_add {
-                    if self.sources.contains_key(&fd) {
-                        continue;
-                    }
-                    if self.backend.register(fd, src.interest).is_ok() {
-                        self.sources.insert(fd, src);
+        if !self.pending_remove.is_empty() {
+            let to_remove = std::mem::take(&mut self.pending_remove);
+            for token in to_remove {
+                let _ = self.remove_io(token);
+            }
+        }
+
+        let mut failed = None;
+        if !self.pending_add.is_empty() {
+            let to_add = std::mem::take(&mut self.pending_add);
+            for token in to_add {
+                let (fd, on_error) = match self.sources.get_mut(token) {
+                    Some(src) => (src.fd, src.on_error.take()),
+                    None => (-1, None),
+                };
+                match self.register_source(token) {
+                    Ok(()) => {
+                        if let Some(src) = self.sources.get_mut(token) {
+                            src.on_error = on_error;
+                        }
                     }
+                    Err(e) => match on_error {
+                        Some(callback) => {
+                            self.run_callback(|| Activity::Io { token, fd }, |lp| callback(lp, e))
+                        }
+                        None => {
+                            failed.get_or_insert(e);
+                        }
+                    },
                 }
             }
         }
+
+        self.run_hooks(HookKind::Check);
+
+        // Run what the wakeup was for before returning, so callers polling a
+        // condition between iterations see its effect.
+        self.run_due_work();
+        match failed {
+            Some(e) => Err(e),
+            None => Ok(dispatched),
+        }
+    }
+
+    fn run_due_work(&mut self) {
+        self.drain_shared_tasks();
+        self.take_remote_timers();
+        self.run_expired_timers();
+        self.run_local_tasks();
     }
 
     fn drain_shared_tasks(&mut self) {
-        while let Ok(t) = self.shared_rx.try_recv() {
+        self.handle.waker.clear();
+        let mut n = 0;
+        while let Some(t) = self.shared_rx.pop() {
+            n += 1;
             self.local_tasks.push_back(t);
+            self.observe(|o| o.task_posted(true));
+        }
+        self.shared_rx.release(n);
+    }
+
+    fn take_remote_timers(&mut self) {
+        for t in self.handle.timers.take() {
+            if t.cancelled.load(Ordering::Acquire) {
+                continue;
+            }
+            let id = self
+                .timers
+                .insert_with(t.when, TimerTask::Once(t.task), t.cancelled);
+            let _ = t.id.set(id);
         }
     }
 
     fn run_local_tasks(&mut self) {
         while let Some(task) = self.local_tasks.pop_front() {
-            task(self);
+            let start = Instant::now();
+            self.run_callback(|| Activity::Task, task);
+            let took = start.elapsed();
+            self.metrics.callback_seconds.observe_duration(took);
+            self.observe(|o| o.task_run(took));
             if self.exit_requested {
                 break;
             }
@@ -218,26 +858,170 @@ impl EventLoop {
 
     fn run_expired_timers(&mut self) {
         let now = Instant::now();
-        while let Some(Reverse(t)) = self.timers.peek() {
-            if t.when > now {
-                break;
+        while let Some(t) = self.timers.pop_expired(now) {
+            let start = Instant::now();
+            let late = start.saturating_duration_since(t.when);
+            self.metrics.timer_lag_seconds.observe_duration(late);
+            match t.task {
+                TimerTask::Once(task) => self.run_callback(|| Activity::Timer(t.id), task),
+                TimerTask::Repeating {
+                    period,
+                    behavior,
+                    mut f,
+                } => {
+                    self.run_callback(|| Activity::Timer(t.id), |lp| f(lp));
+                    let next = behavior.next_deadline(t.when, period, Instant::now());
+                    let task = TimerTask::Repeating {
+                        period,
+                        behavior,
+                        f,
+                    };
+                    self.timers.rearm(t.id, task, next);
+                }
             }
-            let Reverse(t) = self.timers.pop().unwrap();
-            (t.task)(self);
+            let took = start.elapsed();
+            self.metrics.callback_seconds.observe_duration(took);
+            self.observe(|o| o.timer_fired(t.id, late, took));
             if self.exit_requested {
                 return;
             }
         }
     }
 
-    fn compute_timeout(&self) -> Option<Duration> {
-        if !self.local_tasks.is_empty() {
+    fn compute_timeout(&mut self) -> Option<Duration> {
+        if !self.local_tasks.is_empty() || self.hooks.has_idle() {
             return Some(Duration::from_millis(0));
         }
-        let Some(Reverse(next)) = self.timers.peek() else {
-            return None;
-        };
+        let next = self.timers.next_deadline()?;
         let now = Instant::now();
-        Some(next.when.saturating_duration_since(now))
+        Some(next.saturating_duration_since(now))
+    }
+}
+
+fn trace_observer() -> Option<Box<dyn LoopObserver>> {
+    match std::env::var_os("EVLOOP_TRACE") {
+        Some(v) if v == "json" => Some(Box::new(JsonLinesWriter::stderr())),
+        Some(_) => Some(Box::new(TextLogger::stderr())),
+        None => None,
+    }
+}
+
+struct RestoreHook<'a> {
+    lp: &'a mut EventLoop,
+    hook: Option<PanicHook>,
+}
+
+impl Drop for RestoreHook<'_> {
+    fn drop(&mut self) {
+        // The hook may have replaced itself.
+        if self.lp.panic_hook.is_none() {
+            self.lp.panic_hook = self.hook.take();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::io::Write;
+    use std::os::unix::net::UnixStream;
+    use std::rc::Rc;
+
+    fn new_loop() -> (EventLoop, Handle) {
+        EventLoop::new(BackendKind::Epoll).unwrap()
+    }
+
+    // Counts the readable callbacks of one end of a socket pair.
+    fn watch_readable(lp: &mut EventLoop) -> (UnixStream, UnixStream, Rc<Cell<usize>>) {
+        let (a, b) = UnixStream::pair().unwrap();
+        let hits = Rc::new(Cell::new(0));
+        let counter = hits.clone();
+        lp.add_io(a.as_raw_fd(), Interest::Readable, move |_, ready| {
+            if ready.readable {
+                counter.set(counter.get() + 1);
+            }
+        })
+        .unwrap();
+        (a, b, hits)
+    }
+
+    #[test]
+    fn run_once_returns_the_number_of_io_callbacks() {
+        let (mut lp, _handle) = new_loop();
+        let (_a, mut b, hits) = watch_readable(&mut lp);
+        assert_eq!(lp.run_once(Some(Duration::ZERO)).unwrap(), 0);
+
+        b.write_all(b"x").unwrap();
+        assert_eq!(lp.run_once(Some(Duration::from_secs(1))).unwrap(), 1);
+        assert_eq!(hits.get(), 1);
+    }
+
+    #[test]
+    fn run_once_consumes_request_exit() {
+        let (mut lp, _handle) = new_loop();
+        let (_a, mut b, hits) = watch_readable(&mut lp);
+        b.write_all(b"x").unwrap();
+
+        lp.request_exit();
+        assert_eq!(lp.run_once(Some(Duration::from_secs(1))).unwrap(), 0);
+        assert_eq!(hits.get(), 0);
+        assert_eq!(lp.run_once(Some(Duration::from_secs(1))).unwrap(), 1);
+        assert_eq!(hits.get(), 1);
+    }
+
+    #[test]
+    fn run_until_checks_before_every_iteration() {
+        let (mut lp, _handle) = new_loop();
+        let ran = Rc::new(Cell::new(0));
+        let counter = ran.clone();
+        lp.add_idle(move |_| counter.set(counter.get() + 1));
+
+        lp.run_until(|_| true).unwrap();
+        assert_eq!(ran.get(), 0);
+
+        let mut checks = 0;
+        lp.run_until(|_| {
+            checks += 1;
+            checks > 3
+        })
+        .unwrap();
+        assert_eq!(ran.get(), 3);
+    }
+
+    #[test]
+    fn run_stops_on_request_exit_from_another_thread() {
+        let (mut lp, handle) = new_loop();
+        let poster = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(10));
+            handle.post(|lp| lp.request_exit()).unwrap();
+        });
+        lp.run_for(Duration::MAX).unwrap();
+        poster.join().unwrap();
+
+        // The request was consumed: the next run lasts its full duration.
+        let start = Instant::now();
+        lp.run_for(Duration::from_millis(20)).unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(20));
+    }
+
+    #[test]
+    fn stale_token_is_rejected_after_the_fd_is_registered_again() {
+        let (mut lp, _handle) = new_loop();
+        let (a, _b) = UnixStream::pair().unwrap();
+        let old = lp
+            .add_io(a.as_raw_fd(), Interest::Readable, |_, _| {})
+            .unwrap();
+        lp.remove_io(old).unwrap();
+        let new = lp
+            .add_io(a.as_raw_fd(), Interest::Readable, |_, _| {})
+            .unwrap();
+
+        assert_ne!(old, new);
+        let err = lp.modify_io(old, Interest::ReadWrite).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        // Removing through the stale token leaves the new registration alone.
+        lp.remove_io(old).unwrap();
+        assert!(lp.modify_io(new, Interest::ReadWrite).is_ok());
     }
 }
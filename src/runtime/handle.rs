@@ -1,22 +1,410 @@
+use super::inject::{self, Refused};
+use super::metrics::SharedCounters;
+use super::timer::{RemoteTimerHandle, RemoteTimers};
 use super::waker::Waker;
 use super::Task;
+use std::fmt;
+use std::future::{self, Future};
 use std::io;
-use std::sync::mpsc;
+use std::pin::Pin;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::task::{self, Context, Poll};
+use std::thread::{self, ThreadId};
+use std::time::{Duration, Instant};
 
 #[derive(Clone)]
 pub struct Handle {
-    pub(crate) tx: mpsc::Sender<Task>,
+    pub(crate) tx: inject::Sender,
     pub(crate) waker: Waker,
+    pub(crate) counters: Arc<SharedCounters>,
+    pub(crate) timers: Arc<RemoteTimers>,
+    // The loop cannot move between threads, so this is where it runs.
+    pub(crate) loop_thread: ThreadId,
 }
 
 impl Handle {
+    /// Queues `f` to run on the loop. Fails with `WouldBlock` while a bounded
+    /// queue is full, see `EventLoop::set_queue_capacity`.
     pub fn post<F>(&self, f: F) -> io::Result<()>
     where
         F: FnOnce(&mut super::EventLoop) + Send + 'static,
     {
-        self.tx.send(Box::new(f)).map_err(|_| {
-            io::Error::new(io::ErrorKind::BrokenPipe, "event loop task channel closed")
-        })?;
+        self.tx.reserve().map_err(refused)?;
+        self.send(Box::new(f))
+    }
+
+    /// Like `post`, but hands `f` back if it cannot be queued.
+    pub fn try_post<F>(&self, f: F) -> Result<(), TryPostError<F>>
+    where
+        F: FnOnce(&mut super::EventLoop) + Send + 'static,
+    {
+        match self.tx.reserve() {
+            Ok(()) => {
+                // `f` is queued even if the wakeup failed; the loop runs it
+                // on its next iteration.
+                let _ = self.send(Box::new(f));
+                Ok(())
+            }
+            Err(Refused::Full) => Err(TryPostError::Full(f)),
+            Err(Refused::Closed) => Err(TryPostError::Closed(f)),
+        }
+    }
+
+    /// Like `post`, but blocks the calling thread while the queue is full.
+    /// Don't call it on the loop thread: nothing would make room.
+    pub fn post_wait<F>(&self, f: F) -> io::Result<()>
+    where
+        F: FnOnce(&mut super::EventLoop) + Send + 'static,
+    {
+        self.tx.reserve_blocking().map_err(refused)?;
+        self.send(Box::new(f))
+    }
+
+    /// Like `post`, but waits asynchronously while the queue is full.
+    pub async fn post_async<F>(&self, f: F) -> io::Result<()>
+    where
+        F: FnOnce(&mut super::EventLoop) + Send + 'static,
+    {
+        future::poll_fn(|cx| self.tx.poll_reserve(cx.waker()))
+            .await
+            .map_err(refused)?;
+        self.send(Box::new(f))
+    }
+
+    /// Runs `f` on the loop after `delay`, measured from now.
+    pub fn post_delayed<F>(&self, delay: Duration, f: F) -> io::Result<RemoteTimerHandle>
+    where
+        F: FnOnce(&mut super::EventLoop) + Send + 'static,
+    {
+        self.post_at(Instant::now() + delay, f)
+    }
+
+    /// Runs `f` on the loop at `when`. Goes straight to the loop's timers,
+    /// bypassing the task queue, and only wakes the loop if it would
+    /// otherwise sleep past `when`.
+    pub fn post_at<F>(&self, when: Instant, f: F) -> io::Result<RemoteTimerHandle>
+    where
+        F: FnOnce(&mut super::EventLoop) + Send + 'static,
+    {
+        if self.tx.is_closed() {
+            return Err(refused(Refused::Closed));
+        }
+        let (timer, wake) = self.timers.push(when, Box::new(f));
+        if wake {
+            self.waker.wake()?;
+        }
+        Ok(timer)
+    }
+
+    /// `post` for the runtime's own bookkeeping (wakeups, cancellations),
+    /// which must not be lost to a full queue.
+    pub(crate) fn post_unbounded<F>(&self, f: F) -> io::Result<()>
+    where
+        F: FnOnce(&mut super::EventLoop) + Send + 'static,
+    {
+        self.tx.reserve_unbounded().map_err(refused)?;
+        self.send(Box::new(f))
+    }
+
+    fn send(&self, task: Task) -> io::Result<()> {
+        self.tx.push(task);
         self.waker.wake()
     }
+
+    /// Runs `f` on the loop and resolves to its result. While a bounded
+    /// queue is full, `f` is queued once the future finds room. Fails if the
+    /// loop is gone before running `f`, or `f` panics.
+    pub fn call<F, R>(&self, f: F) -> CallFuture<R>
+    where
+        F: FnOnce(&mut super::EventLoop) -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        let (shared, task) = call_task(f);
+        let pending = match self.tx.reserve() {
+            Ok(()) => {
+                shared.send(self, task);
+                None
+            }
+            Err(Refused::Full) => Some((task, self.clone())),
+            Err(r @ Refused::Closed) => {
+                shared.fail(refused(r));
+                None
+            }
+        };
+        CallFuture { shared, pending }
+    }
+
+    /// Blocking `call` for threads outside the loop, which also waits for
+    /// room in a full queue. Fails with `ErrorKind::Deadlock` on the loop's
+    /// own thread, where nothing could run `f` while it waits.
+    pub fn call_blocking<F, R>(&self, f: F) -> io::Result<R>
+    where
+        F: FnOnce(&mut super::EventLoop) -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        if thread::current().id() == self.loop_thread {
+            return Err(io::Error::new(
+                io::ErrorKind::Deadlock,
+                "call_blocking on the event loop thread",
+            ));
+        }
+        let (shared, task) = call_task(f);
+        self.tx.reserve_blocking().map_err(refused)?;
+        shared.send(self, task);
+        let mut state = shared.lock();
+        loop {
+            if let Some(result) = state.result.take() {
+                return result;
+            }
+            state = shared
+                .done
+                .wait(state)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+    }
+}
+
+fn call_task<F, R>(f: F) -> (Arc<CallShared<R>>, Task)
+where
+    F: FnOnce(&mut super::EventLoop) -> R + Send + 'static,
+    R: Send + 'static,
+{
+    let shared = Arc::new(CallShared {
+        state: Mutex::new(CallState {
+            result: None,
+            waker: None,
+        }),
+        done: Condvar::new(),
+    });
+    let completion = Completion(shared.clone());
+    let task: Task = Box::new(move |loop_ref| completion.complete(Ok(f(loop_ref))));
+    (shared, task)
+}
+
+/// Error from `Handle::try_post`, carrying back the closure.
+pub enum TryPostError<F> {
+    /// The queue is at its capacity.
+    Full(F),
+    /// The loop is gone.
+    Closed(F),
+}
+
+impl<F> TryPostError<F> {
+    pub fn into_inner(self) -> F {
+        match self {
+            TryPostError::Full(f) | TryPostError::Closed(f) => f,
+        }
+    }
+
+    pub fn is_full(&self) -> bool {
+        matches!(self, TryPostError::Full(_))
+    }
+}
+
+impl<F> fmt::Debug for TryPostError<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TryPostError::Full(_) => f.write_str("Full(..)"),
+            TryPostError::Closed(_) => f.write_str("Closed(..)"),
+        }
+    }
+}
+
+impl<F> fmt::Display for TryPostError<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TryPostError::Full(_) => f.write_str(FULL),
+            TryPostError::Closed(_) => f.write_str(CLOSED),
+        }
+    }
+}
+
+impl<F> std::error::Error for TryPostError<F> {}
+
+impl<F> From<TryPostError<F>> for io::Error {
+    fn from(e: TryPostError<F>) -> Self {
+        refused(match e {
+            TryPostError::Full(_) => Refused::Full,
+            TryPostError::Closed(_) => Refused::Closed,
+        })
+    }
+}
+
+const FULL: &str = "event loop task queue full";
+const CLOSED: &str = "event loop task channel closed";
+
+fn refused(r: Refused) -> io::Error {
+    match r {
+        Refused::Full => io::Error::new(io::ErrorKind::WouldBlock, FULL),
+        Refused::Closed => io::Error::new(io::ErrorKind::BrokenPipe, CLOSED),
+    }
+}
+
+struct CallState<R> {
+    result: Option<io::Result<R>>,
+    waker: Option<task::Waker>,
+}
+
+struct CallShared<R> {
+    state: Mutex<CallState<R>>,
+    done: Condvar,
+}
+
+impl<R> CallShared<R> {
+    fn lock(&self) -> MutexGuard<'_, CallState<R>> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Queues `task` into a slot already reserved on `handle`.
+    fn send(&self, handle: &Handle, task: Task) {
+        if let Err(e) = handle.send(task) {
+            // The task is queued but the loop may not notice; report the
+            // wakeup error rather than wait for a result.
+            self.fail(e);
+        }
+    }
+
+    fn fail(&self, e: io::Error) {
+        let mut state = self.lock();
+        if state.result.is_none() {
+            state.result = Some(Err(e));
+        }
+    }
+}
+
+/// Sending half of a call, owned by the posted task. Dropping it without a
+/// result, because the loop dropped the task or `f` panicked, fails the call.
+struct Completion<R>(Arc<CallShared<R>>);
+
+impl<R> Completion<R> {
+    fn complete(self, result: io::Result<R>) {
+        self.set(result);
+    }
+
+    fn set(&self, result: io::Result<R>) {
+        let waker = {
+            let mut state = self.0.lock();
+            if state.result.is_some() {
+                return;
+            }
+            state.result = Some(result);
+            state.waker.take()
+        };
+        self.0.done.notify_all();
+        if let Some(w) = waker {
+            w.wake();
+        }
+    }
+}
+
+impl<R> Drop for Completion<R> {
+    fn drop(&mut self) {
+        let msg = if thread::panicking() {
+            "call panicked on the event loop"
+        } else {
+            "event loop dropped the call"
+        };
+        self.set(Err(io::Error::new(io::ErrorKind::BrokenPipe, msg)));
+    }
+}
+
+/// Result of `Handle::call`.
+pub struct CallFuture<R> {
+    shared: Arc<CallShared<R>>,
+    // The task and its handle while waiting for room in a full queue.
+    pending: Option<(Task, Handle)>,
+}
+
+impl<R> Future for CallFuture<R> {
+    type Output = io::Result<R>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if let Some((_, handle)) = self.pending.as_ref() {
+            match handle.tx.poll_reserve(cx.waker()) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(Err(r)) => {
+                    self.pending = None;
+                    return Poll::Ready(Err(refused(r)));
+                }
+                Poll::Ready(Ok(())) => {
+                    let (task, handle) = self.pending.take().expect("pending call");
+                    self.shared.send(&handle, task);
+                }
+            }
+        }
+        let mut state = self.shared.lock();
+        if let Some(result) = state.result.take() {
+            return Poll::Ready(result);
+        }
+        state.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::{BackendKind, EventLoop};
+
+    fn new_loop() -> (EventLoop, Handle) {
+        EventLoop::new(BackendKind::Epoll).unwrap()
+    }
+
+    #[test]
+    fn try_post_reports_full_and_closed() {
+        let (mut lp, handle) = new_loop();
+        lp.set_queue_capacity(Some(2));
+        assert!(handle.try_post(|_| {}).is_ok());
+        assert!(handle.try_post(|_| {}).is_ok());
+
+        let err = handle.try_post(|lp| lp.request_exit()).unwrap_err();
+        assert!(err.is_full());
+        let f = err.into_inner();
+        assert_eq!(
+            io::Error::from(TryPostError::Full(())).kind(),
+            io::ErrorKind::WouldBlock
+        );
+
+        lp.run_once(Some(Duration::ZERO)).unwrap();
+        assert!(handle.try_post(f).is_ok());
+
+        drop(lp);
+        let err = handle.try_post(|_| {}).unwrap_err();
+        assert!(!err.is_full());
+        assert_eq!(io::Error::from(err).kind(), io::ErrorKind::BrokenPipe);
+    }
+
+    #[test]
+    fn call_blocking_on_the_loop_thread_is_a_deadlock() {
+        let (_lp, handle) = new_loop();
+        let err = handle.call_blocking(|_| ()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Deadlock);
+    }
+
+    #[test]
+    fn call_blocking_waits_for_room_and_returns_the_result() {
+        let (mut lp, handle) = new_loop();
+        lp.set_queue_capacity(Some(1));
+        handle.post(|_| {}).unwrap();
+
+        let remote = handle.clone();
+        let caller = thread::spawn(move || remote.call_blocking(|lp| lp.id()));
+        while !caller.is_finished() {
+            lp.run_for(Duration::from_millis(10)).unwrap();
+        }
+        assert_eq!(caller.join().unwrap().unwrap(), lp.id());
+    }
+
+    #[test]
+    fn call_blocking_fails_when_the_loop_drops_the_call() {
+        let (lp, handle) = new_loop();
+        let remote = handle.clone();
+        let caller = thread::spawn(move || remote.call_blocking(|_| ()));
+        while handle.tx.len() == 0 {
+            thread::yield_now();
+        }
+        drop(lp);
+        let err = caller.join().unwrap().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+    }
 }
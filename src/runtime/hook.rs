@@ -0,0 +1,91 @@
+use super::slab::Slab;
+use super::Token;
+
+/// Identifies a prepare, check or idle hook on its `EventLoop`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct HookId(Token);
+
+pub(crate) type HookCallback = Box<dyn FnMut(&mut super::EventLoop) + 'static>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum HookKind {
+    Prepare,
+    Check,
+    Idle,
+}
+
+struct Hook {
+    kind: HookKind,
+    callback: Option<HookCallback>,
+}
+
+/// Hooks of each kind run in registration order.
+pub(crate) struct Hooks {
+    entries: Slab<Hook>,
+    prepare: Vec<Token>,
+    check: Vec<Token>,
+    idle: Vec<Token>,
+}
+
+impl Hooks {
+    pub(crate) fn new() -> Self {
+        Self {
+            entries: Slab::new(),
+            prepare: Vec::new(),
+            check: Vec::new(),
+            idle: Vec::new(),
+        }
+    }
+
+    pub(crate) fn insert(&mut self, kind: HookKind, callback: HookCallback) -> HookId {
+        let token = self.entries.insert(Hook {
+            kind,
+            callback: Some(callback),
+        });
+        self.list_mut(kind).push(token);
+        HookId(token)
+    }
+
+    pub(crate) fn remove(&mut self, id: HookId) -> bool {
+        let Some(hook) = self.entries.remove(id.0) else {
+            return false;
+        };
+        self.list_mut(hook.kind).retain(|&t| t != id.0);
+        true
+    }
+
+    /// Snapshot of the hooks of `kind`, so hooks may add or remove hooks
+    /// while they run.
+    pub(crate) fn ids(&self, kind: HookKind) -> Vec<HookId> {
+        let list = match kind {
+            HookKind::Prepare => &self.prepare,
+            HookKind::Check => &self.check,
+            HookKind::Idle => &self.idle,
+        };
+        list.iter().map(|&t| HookId(t)).collect()
+    }
+
+    pub(crate) fn has_idle(&self) -> bool {
+        !self.idle.is_empty()
+    }
+
+    pub(crate) fn take(&mut self, id: HookId) -> Option<HookCallback> {
+        self.entries.get_mut(id.0)?.callback.take()
+    }
+
+    /// Returns a callback taken with `take`, unless the hook was removed
+    /// meanwhile.
+    pub(crate) fn put_back(&mut self, id: HookId, callback: HookCallback) {
+        if let Some(hook) = self.entries.get_mut(id.0) {
+            hook.callback = Some(callback);
+        }
+    }
+
+    fn list_mut(&mut self, kind: HookKind) -> &mut Vec<Token> {
+        match kind {
+            HookKind::Prepare => &mut self.prepare,
+            HookKind::Check => &mut self.check,
+            HookKind::Idle => &mut self.idle,
+        }
+    }
+}
@@ -0,0 +1,384 @@
+//! Cross-thread task queue feeding the loop from its `Handle`s: a lock-free
+//! multi-producer, single-consumer linked list with an optional capacity.
+
+use super::Task;
+
+use std::cell::UnsafeCell;
+use std::ptr;
+use std::sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::task::{Poll, Waker};
+
+const UNBOUNDED: usize = usize::MAX;
+
+struct Node {
+    next: AtomicPtr<Node>,
+    task: Option<Task>,
+}
+
+impl Node {
+    fn alloc(task: Option<Task>) -> *mut Node {
+        Box::into_raw(Box::new(Node {
+            next: AtomicPtr::new(ptr::null_mut()),
+            task,
+        }))
+    }
+}
+
+struct Shared {
+    // Producers swap themselves in at `head`; the consumer follows `tail`,
+    // which always points at an already consumed node.
+    head: AtomicPtr<Node>,
+    tail: UnsafeCell<*mut Node>,
+    // Tasks pushed and not yet released by the consumer. Reserved before the
+    // push, so it bounds what the queue can hold.
+    len: AtomicUsize,
+    capacity: AtomicUsize,
+    closed: AtomicBool,
+    // Serialises draining once the receiver is gone, see `Sender::push`.
+    drain: Mutex<()>,
+    // Producers waiting for room: blocked threads on `space`, async ones in
+    // `waiters`. `waiting` counts both so the consumer can skip the lock.
+    waiting: AtomicUsize,
+    waiters: Mutex<Vec<Waker>>,
+    space: Condvar,
+}
+
+// SAFETY: `tail` is only touched by the single `Receiver`, or under `drain`
+// once it is gone; everything else is atomic or locked.
+unsafe impl Send for Shared {}
+unsafe impl Sync for Shared {}
+
+pub(crate) fn channel() -> (Sender, Receiver) {
+    let stub = Node::alloc(None);
+    let shared = Arc::new(Shared {
+        head: AtomicPtr::new(stub),
+        tail: UnsafeCell::new(stub),
+        len: AtomicUsize::new(0),
+        capacity: AtomicUsize::new(UNBOUNDED),
+        closed: AtomicBool::new(false),
+        drain: Mutex::new(()),
+        waiting: AtomicUsize::new(0),
+        waiters: Mutex::new(Vec::new()),
+        space: Condvar::new(),
+    });
+    (
+        Sender {
+            shared: shared.clone(),
+        },
+        Receiver { shared },
+    )
+}
+
+/// Why a slot could not be reserved.
+pub(crate) enum Refused {
+    Full,
+    Closed,
+}
+
+#[derive(Clone)]
+pub(crate) struct Sender {
+    shared: Arc<Shared>,
+}
+
+impl Sender {
+    /// Claims room for one task, respecting the capacity.
+    pub(crate) fn reserve(&self) -> Result<(), Refused> {
+        let s = &self.shared;
+        if s.closed.load(Ordering::SeqCst) {
+            return Err(Refused::Closed);
+        }
+        let cap = s.capacity.load(Ordering::Relaxed);
+        s.len
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| {
+                (n < cap).then_some(n + 1)
+            })
+            .map(|_| ())
+            .map_err(|_| Refused::Full)
+    }
+
+    /// Claims room for one task regardless of the capacity.
+    pub(crate) fn reserve_unbounded(&self) -> Result<(), Refused> {
+        if self.shared.closed.load(Ordering::SeqCst) {
+            return Err(Refused::Closed);
+        }
+        self.shared.len.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// Queues `task` into a slot claimed with `reserve`.
+    pub(crate) fn push(&self, task: Task) {
+        let s = &self.shared;
+        let node = Node::alloc(Some(task));
+        let prev = s.head.swap(node, Ordering::SeqCst);
+        // SAFETY: `prev` stays alive until the consumer moves past it, which
+        // needs this link first.
+        unsafe { (*prev).next.store(node, Ordering::Release) };
+        if s.closed.load(Ordering::SeqCst) {
+            // The receiver may have finished draining before our swap; nobody
+            // else would drop the task.
+            s.drain_closed();
+        }
+    }
+
+    /// `reserve`, blocking while the queue is full.
+    pub(crate) fn reserve_blocking(&self) -> Result<(), Refused> {
+        let s = &self.shared;
+        loop {
+            match self.reserve() {
+                Err(Refused::Full) => {}
+                r => return r,
+            }
+            let waiters = s.waiters();
+            // Registered before retrying, so a `release` in between either
+            // sees us waiting or leaves room for the retry.
+            s.waiting.fetch_add(1, Ordering::SeqCst);
+            let r = self.reserve();
+            if !matches!(r, Err(Refused::Full)) {
+                s.waiting.fetch_sub(1, Ordering::SeqCst);
+                return r;
+            }
+            drop(
+                s.space
+                    .wait(waiters)
+                    .unwrap_or_else(PoisonError::into_inner),
+            );
+            s.waiting.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+
+    /// `reserve`, registering `waker` for when room frees up while full.
+    pub(crate) fn poll_reserve(&self, waker: &Waker) -> Poll<Result<(), Refused>> {
+        match self.reserve() {
+            Err(Refused::Full) => {}
+            r => return Poll::Ready(r),
+        }
+        let s = &self.shared;
+        let mut waiters = s.waiters();
+        if !waiters.iter().any(|w| w.will_wake(waker)) {
+            waiters.push(waker.clone());
+            s.waiting.fetch_add(1, Ordering::SeqCst);
+        }
+        match self.reserve() {
+            Err(Refused::Full) => Poll::Pending,
+            r => Poll::Ready(r),
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.shared.len.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn is_closed(&self) -> bool {
+        self.shared.closed.load(Ordering::SeqCst)
+    }
+}
+
+pub(crate) struct Receiver {
+    shared: Arc<Shared>,
+}
+
+impl Receiver {
+    /// Takes the oldest task. Its slot stays claimed until `release`, which
+    /// keeps a bounded queue from refilling while the loop drains it.
+    pub(crate) fn pop(&mut self) -> Option<Task> {
+        // SAFETY: `&mut self` makes this the only consumer.
+        unsafe { self.shared.pop() }
+    }
+
+    /// Frees `n` slots taken by `pop` and wakes waiting producers.
+    pub(crate) fn release(&self, n: usize) {
+        if n > 0 {
+            self.shared.release(n);
+        }
+    }
+
+    pub(crate) fn set_capacity(&self, capacity: Option<usize>) {
+        let s = &self.shared;
+        s.capacity
+            .store(capacity.unwrap_or(UNBOUNDED), Ordering::Relaxed);
+        // A larger capacity may let waiting producers in.
+        s.wake_waiters();
+    }
+}
+
+impl Drop for Receiver {
+    fn drop(&mut self) {
+        self.shared.closed.store(true, Ordering::SeqCst);
+        self.shared.drain_closed();
+        self.shared.wake_waiters();
+    }
+}
+
+impl Shared {
+    fn waiters(&self) -> MutexGuard<'_, Vec<Waker>> {
+        self.waiters.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// SAFETY: the caller must be the only consumer.
+    unsafe fn pop(&self) -> Option<Task> {
+        let tail = *self.tail.get();
+        let next = (*tail).next.load(Ordering::Acquire);
+        if next.is_null() {
+            // Empty, or a producer is between its swap and its link; it
+            // wakes the loop once linked.
+            return None;
+        }
+        *self.tail.get() = next;
+        drop(Box::from_raw(tail));
+        (*next).task.take()
+    }
+
+    fn release(&self, n: usize) {
+        self.len.fetch_sub(n, Ordering::SeqCst);
+        if self.waiting.load(Ordering::SeqCst) > 0 {
+            self.wake_waiters();
+        }
+    }
+
+    fn wake_waiters(&self) {
+        let wakers = {
+            let mut w = self.waiters();
+            self.waiting.fetch_sub(w.len(), Ordering::SeqCst);
+            self.space.notify_all();
+            std::mem::take(&mut *w)
+        };
+        for w in wakers {
+            w.wake();
+        }
+    }
+
+    /// Drops every queued task after the receiver is gone, waiting out
+    /// producers caught between their swap and link.
+    fn drain_closed(&self) {
+        let mut dropped = Vec::new();
+        {
+            let _guard = self.drain.lock().unwrap_or_else(PoisonError::into_inner);
+            loop {
+                // SAFETY: with the receiver gone, `drain` makes us the consumer.
+                match unsafe { self.pop() } {
+                    Some(task) => dropped.push(task),
+                    None if self.head.load(Ordering::SeqCst) == unsafe { *self.tail.get() } => {
+                        break;
+                    }
+                    None => std::hint::spin_loop(),
+                }
+            }
+        }
+        // Outside the lock: a task's destructor may post again.
+        drop(dropped);
+    }
+}
+
+impl Drop for Shared {
+    fn drop(&mut self) {
+        let mut node = *self.tail.get_mut();
+        while !node.is_null() {
+            // SAFETY: no other references remain; every node is owned here.
+            let boxed = unsafe { Box::from_raw(node) };
+            node = boxed.next.load(Ordering::Relaxed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+
+    // Records its number when dropped, standing in for a task's state.
+    struct Marker(usize, Arc<Mutex<Vec<usize>>>);
+
+    impl Drop for Marker {
+        fn drop(&mut self) {
+            self.1.lock().unwrap().push(self.0);
+        }
+    }
+
+    fn task(n: usize, log: &Arc<Mutex<Vec<usize>>>) -> Task {
+        let marker = Marker(n, log.clone());
+        Box::new(move |_| drop(marker))
+    }
+
+    #[test]
+    fn pops_in_fifo_order() {
+        let (tx, mut rx) = channel();
+        let log = Arc::new(Mutex::new(Vec::new()));
+        for n in 0..5 {
+            assert!(tx.reserve().is_ok());
+            tx.push(task(n, &log));
+        }
+        while let Some(t) = rx.pop() {
+            drop(t);
+        }
+        assert_eq!(*log.lock().unwrap(), [0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn capacity_counts_popped_tasks_until_released() {
+        let (tx, mut rx) = channel();
+        let log = Arc::new(Mutex::new(Vec::new()));
+        rx.set_capacity(Some(2));
+        for n in 0..2 {
+            assert!(tx.reserve().is_ok());
+            tx.push(task(n, &log));
+        }
+        assert!(matches!(tx.reserve(), Err(Refused::Full)));
+        assert!(tx.reserve_unbounded().is_ok());
+        tx.push(task(2, &log));
+        assert_eq!(tx.len(), 3);
+
+        let popped: Vec<_> = std::iter::from_fn(|| rx.pop()).collect();
+        assert_eq!(popped.len(), 3);
+        assert!(matches!(tx.reserve(), Err(Refused::Full)));
+        rx.release(popped.len());
+        assert!(tx.reserve().is_ok());
+    }
+
+    #[test]
+    fn dropping_the_receiver_closes_and_drops_queued_tasks() {
+        let (tx, rx) = channel();
+        let log = Arc::new(Mutex::new(Vec::new()));
+        assert!(tx.reserve().is_ok());
+        tx.push(task(0, &log));
+        drop(rx);
+        assert!(tx.is_closed());
+        assert_eq!(*log.lock().unwrap(), [0]);
+        assert!(matches!(tx.reserve(), Err(Refused::Closed)));
+        assert!(matches!(tx.reserve_blocking(), Err(Refused::Closed)));
+    }
+
+    #[test]
+    fn blocked_producers_wake_on_release_and_close() {
+        let (tx, mut rx) = channel();
+        let log = Arc::new(Mutex::new(Vec::new()));
+        rx.set_capacity(Some(1));
+        assert!(tx.reserve().is_ok());
+        tx.push(task(0, &log));
+
+        let admitted = Arc::new(AtomicUsize::new(0));
+        let producers: Vec<_> = (0..2)
+            .map(|_| {
+                let (tx, admitted) = (tx.clone(), admitted.clone());
+                std::thread::spawn(move || {
+                    let r = tx.reserve_blocking();
+                    if r.is_ok() {
+                        admitted.fetch_add(1, Ordering::SeqCst);
+                    }
+                    r.is_ok()
+                })
+            })
+            .collect();
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        assert_eq!(admitted.load(Ordering::SeqCst), 0);
+
+        drop(rx.pop());
+        rx.release(1);
+        while admitted.load(Ordering::SeqCst) == 0 {
+            std::thread::yield_now();
+        }
+        drop(rx);
+        let results: Vec<_> = producers.into_iter().map(|p| p.join().unwrap()).collect();
+        assert_eq!(results.iter().filter(|ok| **ok).count(), 1);
+    }
+}
@@ -1,29 +1,62 @@
-use super::Handle;
+use super::{EventLoop, Handle, Interest, Token};
+use crate::async_rt::try_with_current_loop;
 use std::io;
-use std::os::unix::io::RawFd;
 
 pub struct IoWatcher {
-    fd: RawFd,
+    token: Token,
     handle: Handle,
+    loop_id: u64,
     active: bool,
 }
 
 impl IoWatcher {
-    pub(crate) fn new(fd: RawFd, handle: Handle) -> Self {
+    pub(crate) fn new(token: Token, handle: Handle, loop_id: u64) -> Self {
         Self {
-            fd,
+            token,
             handle,
+            loop_id,
             active: true,
         }
     }
 
+    pub fn token(&self) -> Token {
+        self.token
+    }
+
+    pub fn set_interest(&self, interest: Interest) -> io::Result<()> {
+        let token = self.token;
+        self.on_loop(move |loop_ref| {
+            let _ = loop_ref.modify_io(token, interest);
+        })
+    }
+
     pub fn stop(mut self) -> io::Result<()> {
         self.active = false;
-        let fd = self.fd;
-        self.handle.post(move |loop_ref| {
-            let _ = loop_ref.remove_io(fd);
+        let token = self.token;
+        self.on_loop(move |loop_ref| {
+            let _ = loop_ref.remove_io(token);
         })
     }
+
+    // Runs `f` right away from a task on the watcher's own loop, and posts it
+    // to the loop from anywhere else.
+    fn on_loop<F>(&self, f: F) -> io::Result<()>
+    where
+        F: FnOnce(&mut EventLoop) + Send + 'static,
+    {
+        let mut f = Some(f);
+        try_with_current_loop(|loop_ref| {
+            if loop_ref.id() == self.loop_id {
+                if let Some(f) = f.take() {
+                    f(loop_ref);
+                }
+            }
+        });
+        match f {
+            Some(f) => self.handle.post_unbounded(f),
+            None => Ok(()),
+        }
+    }
 }
 
 impl Drop for IoWatcher {
@@ -31,9 +64,9 @@ impl Drop for IoWatcher {
         if !self.active {
             return;
         }
-        let fd = self.fd;
-        let _ = self.handle.post(move |loop_ref| {
-            let _ = loop_ref.remove_io(fd);
+        let token = self.token;
+        let _ = self.on_loop(move |loop_ref| {
+            let _ = loop_ref.remove_io(token);
         });
     }
 }
@@ -0,0 +1,189 @@
+use std::fmt::Write;
+use std::sync::atomic::AtomicU64;
+use std::time::Duration;
+
+const SECONDS_BOUNDS: &[f64] = &[
+    0.000_01, 0.000_05, 0.000_1, 0.000_5, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0,
+];
+const COUNT_BOUNDS: &[f64] = &[
+    0.0, 1.0, 2.0, 4.0, 8.0, 16.0, 32.0, 64.0, 128.0, 256.0, 1024.0,
+];
+
+/// Histogram with fixed bucket upper bounds, laid out like Prometheus'.
+#[derive(Debug, Clone)]
+pub struct Histogram {
+    bounds: &'static [f64],
+    // Per bucket, not cumulative; the last one is +Inf.
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn new(bounds: &'static [f64]) -> Self {
+        Self {
+            bounds,
+            counts: vec![0; bounds.len() + 1],
+            sum: 0.0,
+            count: 0,
+        }
+    }
+
+    pub(crate) fn observe(&mut self, v: f64) {
+        let idx = self.bounds.partition_point(|&b| b < v);
+        self.counts[idx] += 1;
+        self.sum += v;
+        self.count += 1;
+    }
+
+    pub(crate) fn observe_duration(&mut self, d: Duration) {
+        self.observe(d.as_secs_f64());
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn sum(&self) -> f64 {
+        self.sum
+    }
+
+    /// Cumulative `(upper bound, count)` pairs, ending with `f64::INFINITY`.
+    pub fn buckets(&self) -> impl Iterator<Item = (f64, u64)> + '_ {
+        let bounds = self.bounds.iter().copied().chain([f64::INFINITY]);
+        bounds.zip(self.counts.iter().scan(0, |acc, &n| {
+            *acc += n;
+            Some(*acc)
+        }))
+    }
+}
+
+/// Counters shared between a loop and its `Handle`s and `Executor`s.
+#[derive(Default)]
+pub(crate) struct SharedCounters {
+    pub(crate) tasks_spawned: AtomicU64,
+    pub(crate) tasks_completed: AtomicU64,
+}
+
+/// Snapshot of an `EventLoop`'s metrics, see `EventLoop::metrics`.
+#[derive(Debug, Clone)]
+pub struct LoopMetrics {
+    pub iterations: u64,
+    /// Time blocked in the poller per iteration.
+    pub wait_seconds: Histogram,
+    pub events_per_wait: Histogram,
+    /// Run time of I/O callbacks, timers and queued tasks.
+    pub callback_seconds: Histogram,
+    /// How late timers fired relative to their deadline.
+    pub timer_lag_seconds: Histogram,
+    /// Tasks posted through a `Handle` that the loop has not picked up yet.
+    pub shared_queue_depth: usize,
+    pub registered_sources: usize,
+    /// `Executor` tasks spawned but not finished, including tasks nobody
+    /// wakes anymore.
+    pub tasks_live: u64,
+    pub tasks_completed: u64,
+}
+
+impl LoopMetrics {
+    pub(crate) fn new() -> Self {
+        Self {
+            iterations: 0,
+            wait_seconds: Histogram::new(SECONDS_BOUNDS),
+            events_per_wait: Histogram::new(COUNT_BOUNDS),
+            callback_seconds: Histogram::new(SECONDS_BOUNDS),
+            timer_lag_seconds: Histogram::new(SECONDS_BOUNDS),
+            shared_queue_depth: 0,
+            registered_sources: 0,
+            tasks_live: 0,
+            tasks_completed: 0,
+        }
+    }
+
+    /// Renders the metrics in the Prometheus text exposition format, with
+    /// names prefixed by `evloop_`.
+    pub fn to_prometheus(&self) -> String {
+        let mut out = String::new();
+        counter(
+            &mut out,
+            "iterations_total",
+            "Loop iterations.",
+            self.iterations,
+        );
+        histogram(
+            &mut out,
+            "wait_seconds",
+            "Time blocked in the poller.",
+            &self.wait_seconds,
+        );
+        histogram(
+            &mut out,
+            "events_per_wait",
+            "Events returned by one poller wait.",
+            &self.events_per_wait,
+        );
+        histogram(
+            &mut out,
+            "callback_seconds",
+            "Run time of I/O callbacks, timers and tasks.",
+            &self.callback_seconds,
+        );
+        histogram(
+            &mut out,
+            "timer_lag_seconds",
+            "Delay between a timer's deadline and its firing.",
+            &self.timer_lag_seconds,
+        );
+        gauge(
+            &mut out,
+            "shared_queue_depth",
+            "Tasks posted from other threads and not yet picked up.",
+            self.shared_queue_depth as u64,
+        );
+        gauge(
+            &mut out,
+            "registered_sources",
+            "Live I/O registrations.",
+            self.registered_sources as u64,
+        );
+        gauge(
+            &mut out,
+            "tasks_live",
+            "Executor tasks spawned and not finished.",
+            self.tasks_live,
+        );
+        counter(
+            &mut out,
+            "tasks_completed_total",
+            "Executor tasks finished.",
+            self.tasks_completed,
+        );
+        out
+    }
+}
+
+fn counter(out: &mut String, name: &str, help: &str, v: u64) {
+    let _ = writeln!(out, "# HELP evloop_{name} {help}");
+    let _ = writeln!(out, "# TYPE evloop_{name} counter");
+    let _ = writeln!(out, "evloop_{name} {v}");
+}
+
+fn gauge(out: &mut String, name: &str, help: &str, v: u64) {
+    let _ = writeln!(out, "# HELP evloop_{name} {help}");
+    let _ = writeln!(out, "# TYPE evloop_{name} gauge");
+    let _ = writeln!(out, "evloop_{name} {v}");
+}
+
+fn histogram(out: &mut String, name: &str, help: &str, h: &Histogram) {
+    let _ = writeln!(out, "# HELP evloop_{name} {help}");
+    let _ = writeln!(out, "# TYPE evloop_{name} histogram");
+    for (le, n) in h.buckets() {
+        if le.is_infinite() {
+            let _ = writeln!(out, "evloop_{name}_bucket{{le=\"+Inf\"}} {n}");
+        } else {
+            let _ = writeln!(out, "evloop_{name}_bucket{{le=\"{le}\"}} {n}");
+        }
+    }
+    let _ = writeln!(out, "evloop_{name}_sum {}", h.sum);
+    let _ = writeln!(out, "evloop_{name}_count {}", h.count);
+}
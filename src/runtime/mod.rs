@@ -1,16 +1,33 @@
 mod backend;
 mod event_loop;
 mod handle;
+mod hook;
+mod inject;
 mod io_watcher;
+mod metrics;
+mod observer;
+mod signal;
+mod slab;
 mod timer;
 mod types;
 mod waker;
+mod watchdog;
 
-mod os;
+pub(crate) mod os;
 
+pub use backend::{new_poller, Poller, PollerWaker};
 pub use event_loop::EventLoop;
-pub use handle::Handle;
+pub use handle::{CallFuture, Handle, TryPostError};
+pub use hook::HookId;
 pub use io_watcher::IoWatcher;
-pub use types::{BackendKind, Interest, Ready};
+pub use metrics::{Histogram, LoopMetrics};
+pub use observer::{JsonLinesWriter, LoopObserver, TextLogger};
+pub use signal::SignalId;
+pub use timer::{RemoteTimerHandle, TimerHandle, TimerId};
+pub use types::{
+    BackendKind, Interest, IoMode, MissedTickBehavior, Ready, SignalKind, TimerKind, Token,
+};
+pub use watchdog::{Activity, Stall, Watchdog};
 
-pub(crate) use types::Task;
+pub(crate) use types::{RepeatingTask, Task};
+pub(crate) use waker::set_nonblocking;
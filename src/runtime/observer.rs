@@ -0,0 +1,210 @@
+use super::{Ready, TimerId, Token};
+
+use std::io::{self, Write};
+use std::os::unix::io::RawFd;
+use std::time::{Duration, Instant};
+
+/// Receives notifications about the loop's internals, e.g. for tracing.
+///
+/// Every method defaults to doing nothing and runs on the loop thread, so an
+/// observer should return quickly.
+pub trait LoopObserver {
+    /// A loop iteration begins; `iteration` counts from 1.
+    fn iteration_start(&mut self, _iteration: u64) {}
+
+    /// The loop is about to block in the poller for at most `timeout`.
+    fn wait_enter(&mut self, _timeout: Option<Duration>) {}
+
+    /// The poller returned `events` events after `waited`.
+    fn wait_exit(&mut self, _events: usize, _waited: Duration) {}
+
+    /// The I/O callback of `token` on `fd` ran for `took`.
+    fn dispatch(&mut self, _token: Token, _fd: RawFd, _ready: Ready, _took: Duration) {}
+
+    /// Timer `id` fired `late` after its deadline and its callback ran for
+    /// `took`.
+    fn timer_fired(&mut self, _id: TimerId, _late: Duration, _took: Duration) {}
+
+    /// A task was queued. Tasks posted through a `Handle` are `remote` and
+    /// reported once the loop picks them up.
+    fn task_posted(&mut self, _remote: bool) {}
+
+    /// A queued task ran for `took`.
+    fn task_run(&mut self, _took: Duration) {}
+
+    /// An `Executor` task was spawned, reported when the loop picks up its
+    /// first poll.
+    fn task_spawned(&mut self, _id: u64) {}
+
+    /// An `Executor` task was polled for `took`.
+    fn task_polled(&mut self, _id: u64, _took: Duration) {}
+
+    /// An `Executor` task finished, was cancelled or panicked.
+    fn task_completed(&mut self, _id: u64) {}
+}
+
+/// Writes one human-readable line per notification.
+pub struct TextLogger<W: Write> {
+    out: W,
+}
+
+impl<W: Write> TextLogger<W> {
+    pub fn new(out: W) -> Self {
+        Self { out }
+    }
+}
+
+impl TextLogger<io::Stderr> {
+    pub fn stderr() -> Self {
+        Self::new(io::stderr())
+    }
+}
+
+// Write errors are ignored: tracing must not take the loop down.
+impl<W: Write> LoopObserver for TextLogger<W> {
+    fn iteration_start(&mut self, iteration: u64) {
+        let _ = writeln!(self.out, "evloop: iteration {iteration}");
+    }
+
+    fn wait_enter(&mut self, timeout: Option<Duration>) {
+        let _ = match timeout {
+            Some(t) => writeln!(self.out, "evloop: wait timeout={t:?}"),
+            None => writeln!(self.out, "evloop: wait timeout=none"),
+        };
+    }
+
+    fn wait_exit(&mut self, events: usize, waited: Duration) {
+        let _ = writeln!(self.out, "evloop: woke events={events} waited={waited:?}");
+    }
+
+    fn dispatch(&mut self, token: Token, fd: RawFd, ready: Ready, took: Duration) {
+        let _ = writeln!(
+            self.out,
+            "evloop: dispatch fd={fd} token={token:?} {ready:?} took={took:?}"
+        );
+    }
+
+    fn timer_fired(&mut self, id: TimerId, late: Duration, took: Duration) {
+        let _ = writeln!(self.out, "evloop: timer {id:?} late={late:?} took={took:?}");
+    }
+
+    fn task_posted(&mut self, remote: bool) {
+        let from = if remote { "handle" } else { "loop" };
+        let _ = writeln!(self.out, "evloop: task posted from {from}");
+    }
+
+    fn task_run(&mut self, took: Duration) {
+        let _ = writeln!(self.out, "evloop: task ran took={took:?}");
+    }
+
+    fn task_spawned(&mut self, id: u64) {
+        let _ = writeln!(self.out, "evloop: spawn task#{id}");
+    }
+
+    fn task_polled(&mut self, id: u64, took: Duration) {
+        let _ = writeln!(self.out, "evloop: poll task#{id} took={took:?}");
+    }
+
+    fn task_completed(&mut self, id: u64) {
+        let _ = writeln!(self.out, "evloop: done task#{id}");
+    }
+}
+
+/// Writes one JSON object per line and notification. Every object has an
+/// `event` name and `t_ns`, the time since the writer was created; durations
+/// are integer nanoseconds.
+pub struct JsonLinesWriter<W: Write> {
+    out: W,
+    start: Instant,
+}
+
+impl<W: Write> JsonLinesWriter<W> {
+    pub fn new(out: W) -> Self {
+        Self {
+            out,
+            start: Instant::now(),
+        }
+    }
+
+    fn line(&mut self, event: &str, fields: std::fmt::Arguments<'_>) {
+        let t = self.start.elapsed().as_nanos();
+        let _ = writeln!(self.out, r#"{{"t_ns":{t},"event":"{event}"{fields}}}"#);
+    }
+}
+
+impl JsonLinesWriter<io::Stderr> {
+    pub fn stderr() -> Self {
+        Self::new(io::stderr())
+    }
+}
+
+impl<W: Write> LoopObserver for JsonLinesWriter<W> {
+    fn iteration_start(&mut self, iteration: u64) {
+        self.line(
+            "iteration_start",
+            format_args!(r#","iteration":{iteration}"#),
+        );
+    }
+
+    fn wait_enter(&mut self, timeout: Option<Duration>) {
+        match timeout {
+            Some(t) => {
+                let t = t.as_nanos();
+                self.line("wait_enter", format_args!(r#","timeout_ns":{t}"#))
+            }
+            None => self.line("wait_enter", format_args!(r#","timeout_ns":null"#)),
+        }
+    }
+
+    fn wait_exit(&mut self, events: usize, waited: Duration) {
+        let waited = waited.as_nanos();
+        self.line(
+            "wait_exit",
+            format_args!(r#","events":{events},"waited_ns":{waited}"#),
+        );
+    }
+
+    fn dispatch(&mut self, token: Token, fd: RawFd, ready: Ready, took: Duration) {
+        let (token, took) = (token.to_u64(), took.as_nanos());
+        self.line(
+            "dispatch",
+            format_args!(
+                r#","token":{token},"fd":{fd},"readable":{},"writable":{},"error":{},"hup":{},"took_ns":{took}"#,
+                ready.readable, ready.writable, ready.error, ready.hup
+            ),
+        );
+    }
+
+    fn timer_fired(&mut self, id: TimerId, late: Duration, took: Duration) {
+        let (id, late, took) = (id.0.to_u64(), late.as_nanos(), took.as_nanos());
+        self.line(
+            "timer_fired",
+            format_args!(r#","timer":{id},"late_ns":{late},"took_ns":{took}"#),
+        );
+    }
+
+    fn task_posted(&mut self, remote: bool) {
+        self.line("task_posted", format_args!(r#","remote":{remote}"#));
+    }
+
+    fn task_run(&mut self, took: Duration) {
+        let took = took.as_nanos();
+        self.line("task_run", format_args!(r#","took_ns":{took}"#));
+    }
+
+    fn task_spawned(&mut self, id: u64) {
+        self.line("task_spawned", format_args!(r#","task":{id}"#));
+    }
+
+    fn task_polled(&mut self, id: u64, took: Duration) {
+        let took = took.as_nanos();
+        self.line(
+            "task_polled",
+            format_args!(r#","task":{id},"took_ns":{took}"#),
+        );
+    }
+
+    fn task_completed(&mut self, id: u64) {
+        self.line("task_completed", format_args!(r#","task":{id}"#));
+    }
+}
@@ -1,11 +1,10 @@
 use std::io;
-use std::os::raw::c_int;
+use std::os::raw::{c_int, c_long, c_void};
 use std::os::unix::io::RawFd;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-use crate::runtime::{Interest, Ready};
+use crate::runtime::{Interest, IoMode, Poller, Ready, Token};
 
-#[repr(C)]
 #[derive(Clone, Copy)]
 #[repr(C, packed)]
 struct EpollEvent {
@@ -13,26 +12,45 @@ struct EpollEvent {
     data: u64,
 }
 
+#[repr(C)]
+struct KernelTimespec {
+    tv_sec: i64,
+    tv_nsec: i64,
+}
+
 extern "C" {
     fn epoll_create1(flags: c_int) -> c_int;
     fn epoll_ctl(epfd: c_int, op: c_int, fd: c_int, event: *mut EpollEvent) -> c_int;
     fn epoll_wait(epfd: c_int, events: *mut EpollEvent, maxevents: c_int, timeout: c_int) -> c_int;
     fn close(fd: c_int) -> c_int;
+    fn syscall(num: c_long, ...) -> c_long;
 }
 
+// epoll_pwait2 landed in 5.11 and shares this number on every architecture.
+// Called through syscall(2) so older libcs without the wrapper still link.
+const SYS_EPOLL_PWAIT2: c_long = 441;
+
+const ENOSYS: i32 = 38;
+const EPERM: i32 = 1;
+
 const EPOLL_CLOEXEC: c_int = 0x80000;
 
 const EPOLL_CTL_ADD: c_int = 1;
 const EPOLL_CTL_DEL: c_int = 2;
+const EPOLL_CTL_MOD: c_int = 3;
 
 const EPOLLIN: u32 = 0x001;
 const EPOLLOUT: u32 = 0x004;
 const EPOLLERR: u32 = 0x008;
 const EPOLLHUP: u32 = 0x010;
+const EPOLLEXCLUSIVE: u32 = 1 << 28;
+const EPOLLONESHOT: u32 = 1 << 30;
+const EPOLLET: u32 = 1 << 31;
 
 pub struct EpollBackend {
     epfd: RawFd,
     events: Vec<EpollEvent>,
+    has_pwait2: bool,
 }
 
 impl EpollBackend {
@@ -44,37 +62,77 @@ impl EpollBackend {
         Ok(Self {
             epfd,
             events: vec![EpollEvent { events: 0, data: 0 }; 1024],
+            has_pwait2: true,
         })
     }
 
-    pub fn register(&mut self, fd: RawFd, interest: Interest) -> io::Result<()> {
-        let events = match interest {
+    fn ctl(
+        &mut self,
+        op: c_int,
+        fd: RawFd,
+        token: Token,
+        interest: Interest,
+        mode: IoMode,
+    ) -> io::Result<()> {
+        let mut events = match interest {
             Interest::Readable => EPOLLIN,
             Interest::Writable => EPOLLOUT,
             Interest::ReadWrite => EPOLLIN | EPOLLOUT,
         };
+        if mode.edge_triggered {
+            events |= EPOLLET;
+        }
+        if mode.oneshot {
+            events |= EPOLLONESHOT;
+        }
+        if mode.exclusive {
+            events |= EPOLLEXCLUSIVE;
+        }
         let mut ev = EpollEvent {
             events,
-            data: fd as u64,
+            data: token.to_u64(),
         };
-        let rc = unsafe { epoll_ctl(self.epfd, EPOLL_CTL_ADD, fd as c_int, &mut ev) };
+        let rc = unsafe { epoll_ctl(self.epfd, op, fd as c_int, &mut ev) };
         if rc < 0 {
             return Err(io::Error::last_os_error());
         }
         Ok(())
     }
 
-    pub fn deregister(&mut self, fd: RawFd) -> io::Result<()> {
-        let rc = unsafe { epoll_ctl(self.epfd, EPOLL_CTL_DEL, fd as c_int, std::ptr::null_mut()) };
-        if rc < 0 {
-            return Err(io::Error::last_os_error());
+    fn wait_raw(&mut self, timeout: Option<Duration>) -> io::Result<usize> {
+        if self.has_pwait2 {
+            let ts = timeout.map(|d| KernelTimespec {
+                tv_sec: d.as_secs().min(i64::MAX as u64) as i64,
+                tv_nsec: d.subsec_nanos() as i64,
+            });
+            let ts_ptr = ts
+                .as_ref()
+                .map_or(std::ptr::null(), |t| t as *const KernelTimespec);
+            let rc = unsafe {
+                syscall(
+                    SYS_EPOLL_PWAIT2,
+                    self.epfd as c_long,
+                    self.events.as_mut_ptr(),
+                    self.events.len() as c_int,
+                    ts_ptr,
+                    std::ptr::null::<c_void>(),
+                    0usize,
+                )
+            };
+            if rc >= 0 {
+                return Ok(rc as usize);
+            }
+            let err = io::Error::last_os_error();
+            match err.raw_os_error() {
+                // Old kernel, or a seccomp filter that does not know the call.
+                Some(ENOSYS) | Some(EPERM) => self.has_pwait2 = false,
+                _ => return Err(err),
+            }
         }
-        Ok(())
-    }
 
-    pub fn wait(&mut self, timeout: Option<Duration>) -> io::Result<Vec<(RawFd, Ready)>> {
+        // Round up so a sub-millisecond timeout does not become a busy poll.
         let timeout_ms = timeout
-            .map(|d| d.as_millis().min(i32::MAX as u128) as i32)
+            .map(|d| d.as_nanos().div_ceil(1_000_000).min(i32::MAX as u128) as i32)
             .unwrap_or(-1);
 
         let rc = unsafe {
@@ -88,13 +146,57 @@ impl EpollBackend {
         if rc < 0 {
             return Err(io::Error::last_os_error());
         }
+        Ok(rc as usize)
+    }
+}
+
+impl Poller for EpollBackend {
+    fn register(
+        &mut self,
+        fd: RawFd,
+        token: Token,
+        interest: Interest,
+        mode: IoMode,
+    ) -> io::Result<()> {
+        self.ctl(EPOLL_CTL_ADD, fd, token, interest, mode)
+    }
+
+    fn modify(
+        &mut self,
+        fd: RawFd,
+        token: Token,
+        interest: Interest,
+        mode: IoMode,
+    ) -> io::Result<()> {
+        self.ctl(EPOLL_CTL_MOD, fd, token, interest, mode)
+    }
+
+    fn deregister(&mut self, fd: RawFd, _token: Token) -> io::Result<()> {
+        let rc = unsafe { epoll_ctl(self.epfd, EPOLL_CTL_DEL, fd as c_int, std::ptr::null_mut()) };
+        if rc < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    fn wait(&mut self, mut timeout: Option<Duration>) -> io::Result<Vec<(Token, Ready)>> {
+        let deadline = timeout.and_then(|d| Instant::now().checked_add(d));
+        let rc = loop {
+            match self.wait_raw(timeout) {
+                // Interrupted by a signal handler: wait out the rest.
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {
+                    timeout = deadline.map(|d| d.saturating_duration_since(Instant::now()));
+                }
+                res => break res?,
+            }
+        };
 
         let mut out = Vec::new();
-        for ev in self.events.iter().take(rc as usize) {
+        for ev in self.events.iter().take(rc) {
             let bits = ev.events;
             let data = unsafe { std::ptr::read_unaligned(std::ptr::addr_of!(ev.data)) };
             out.push((
-                data as RawFd,
+                Token::from_u64(data),
                 Ready {
                     readable: (bits & EPOLLIN) != 0,
                     writable: (bits & EPOLLOUT) != 0,
@@ -105,6 +207,10 @@ impl EpollBackend {
         }
         Ok(out)
     }
+
+    fn as_raw_fd(&self) -> Option<RawFd> {
+        Some(self.epfd)
+    }
 }
 
 impl Drop for EpollBackend {
@@ -2,3 +2,6 @@ pub mod unix;
 
 #[cfg(target_os = "linux")]
 pub mod linux;
+
+#[cfg(target_os = "linux")]
+pub mod uring;
@@ -1,10 +1,14 @@
+#[cfg(target_os = "linux")]
+use std::cell::RefCell;
 use std::collections::HashMap;
 use std::io;
 use std::os::raw::{c_int, c_short};
+#[cfg(target_os = "linux")]
+use std::os::raw::{c_long, c_void};
 use std::os::unix::io::RawFd;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-use crate::runtime::{Interest, Ready};
+use crate::runtime::{Interest, IoMode, Poller, Ready, Token};
 
 #[repr(C)]
 #[derive(Clone, Copy, Default)]
@@ -14,8 +18,23 @@ struct PollFd {
     revents: c_short,
 }
 
+#[cfg(target_os = "linux")]
+#[repr(C)]
+struct Timespec {
+    tv_sec: c_long,
+    tv_nsec: c_long,
+}
+
 extern "C" {
+    #[cfg(not(target_os = "linux"))]
     fn poll(fds: *mut PollFd, nfds: usize, timeout_ms: c_int) -> c_int;
+    #[cfg(target_os = "linux")]
+    fn ppoll(
+        fds: *mut PollFd,
+        nfds: usize,
+        timeout: *const Timespec,
+        sigmask: *const c_void,
+    ) -> c_int;
 }
 
 const POLLIN: c_short = 0x0001;
@@ -23,69 +42,169 @@ const POLLOUT: c_short = 0x0004;
 const POLLERR: c_short = 0x0008;
 const POLLHUP: c_short = 0x0010;
 
+struct PollEntry {
+    token: Token,
+    oneshot: bool,
+}
+
 pub struct PollBackend {
     fds: Vec<PollFd>,
-    index: HashMap<RawFd, usize>,
+    entries: Vec<PollEntry>,
+    index: HashMap<Token, usize>,
+    // Mirrors the registrations level-triggered so a host loop can watch one
+    // fd; it is only ever drained, never used to dispatch. Created by the
+    // first `as_raw_fd` so loops that are not embedded don't pay for it.
+    #[cfg(target_os = "linux")]
+    mirror: RefCell<Option<super::linux::EpollBackend>>,
 }
 
 impl PollBackend {
     pub fn new() -> io::Result<Self> {
         Ok(Self {
             fds: Vec::new(),
+            entries: Vec::new(),
             index: HashMap::new(),
+            #[cfg(target_os = "linux")]
+            mirror: RefCell::new(None),
         })
     }
 
-    pub fn register(&mut self, fd: RawFd, interest: Interest) -> io::Result<()> {
-        if self.index.contains_key(&fd) {
-            return Ok(());
+    #[cfg(target_os = "linux")]
+    fn mirror_set(&mut self, fd: RawFd, token: Token, interest: Interest) {
+        if let Some(mirror) = self.mirror.get_mut() {
+            mirror_add(mirror, fd, token, interest);
         }
+    }
 
-        let events = match interest {
-            Interest::Readable => POLLIN,
-            Interest::Writable => POLLOUT,
-            Interest::ReadWrite => POLLIN | POLLOUT,
-        };
+    #[cfg(not(target_os = "linux"))]
+    fn mirror_set(&mut self, _fd: RawFd, _token: Token, _interest: Interest) {}
+
+    #[cfg(target_os = "linux")]
+    fn mirror_unset(&mut self, fd: RawFd, token: Token) {
+        if let Some(mirror) = self.mirror.get_mut() {
+            let _ = mirror.deregister(fd, token);
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn mirror_unset(&mut self, _fd: RawFd, _token: Token) {}
+
+    #[cfg(target_os = "linux")]
+    fn poll_raw(&mut self, timeout: Option<Duration>) -> c_int {
+        let ts = timeout.map(|d| Timespec {
+            tv_sec: d.as_secs().min(c_long::MAX as u64) as c_long,
+            tv_nsec: d.subsec_nanos() as c_long,
+        });
+        let ts_ptr = ts
+            .as_ref()
+            .map_or(std::ptr::null(), |t| t as *const Timespec);
+        unsafe {
+            ppoll(
+                self.fds.as_mut_ptr(),
+                self.fds.len(),
+                ts_ptr,
+                std::ptr::null(),
+            )
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn poll_raw(&mut self, timeout: Option<Duration>) -> c_int {
+        // Round up so a sub-millisecond timeout does not become a busy poll.
+        let timeout_ms = timeout
+            .map(|d| d.as_nanos().div_ceil(1_000_000).min(i32::MAX as u128) as i32)
+            .unwrap_or(-1);
+        unsafe { poll(self.fds.as_mut_ptr(), self.fds.len(), timeout_ms as c_int) }
+    }
+}
+
+impl Poller for PollBackend {
+    fn register(
+        &mut self,
+        fd: RawFd,
+        token: Token,
+        interest: Interest,
+        mode: IoMode,
+    ) -> io::Result<()> {
+        if self.index.contains_key(&token) {
+            return Ok(());
+        }
 
         let pfd = PollFd {
             fd: fd as c_int,
-            events,
+            events: interest_events(interest),
             revents: 0,
         };
         let idx = self.fds.len();
         self.fds.push(pfd);
-        self.index.insert(fd, idx);
+        self.entries.push(PollEntry {
+            token,
+            oneshot: mode.oneshot,
+        });
+        self.index.insert(token, idx);
+        self.mirror_set(fd, token, interest);
+        Ok(())
+    }
+
+    fn modify(
+        &mut self,
+        fd: RawFd,
+        token: Token,
+        interest: Interest,
+        mode: IoMode,
+    ) -> io::Result<()> {
+        let Some(&idx) = self.index.get(&token) else {
+            return Err(io::Error::from(io::ErrorKind::NotFound));
+        };
+        // Also re-arms a oneshot entry that was disarmed after firing.
+        self.fds[idx].fd = fd as c_int;
+        self.fds[idx].events = interest_events(interest);
+        self.entries[idx].oneshot = mode.oneshot;
+        self.mirror_set(fd, token, interest);
         Ok(())
     }
 
-    pub fn deregister(&mut self, fd: RawFd) -> io::Result<()> {
-        let Some(idx) = self.index.remove(&fd) else {
+    fn deregister(&mut self, fd: RawFd, token: Token) -> io::Result<()> {
+        let Some(idx) = self.index.remove(&token) else {
             return Ok(());
         };
+        self.mirror_unset(fd, token);
 
         let last = self.fds.len() - 1;
-        let moved_fd = self.fds[last].fd as RawFd;
-        self.fds.swap(idx, last);
-        self.fds.pop();
+        let moved_token = self.entries[last].token;
+        self.fds.swap_remove(idx);
+        self.entries.swap_remove(idx);
 
         if idx != last {
-            self.index.insert(moved_fd, idx);
+            self.index.insert(moved_token, idx);
         }
         Ok(())
     }
 
-    pub fn wait(&mut self, timeout: Option<Duration>) -> io::Result<Vec<(RawFd, Ready)>> {
-        let timeout_ms = timeout
-            .map(|d| d.as_millis().min(i32::MAX as u128) as i32)
-            .unwrap_or(-1);
-
+    fn wait(&mut self, mut timeout: Option<Duration>) -> io::Result<Vec<(Token, Ready)>> {
         for p in &mut self.fds {
             p.revents = 0;
         }
 
-        let rc = unsafe { poll(self.fds.as_mut_ptr(), self.fds.len(), timeout_ms as c_int) };
-        if rc < 0 {
-            return Err(io::Error::last_os_error());
+        let deadline = timeout.and_then(|d| Instant::now().checked_add(d));
+        let rc = loop {
+            let rc = self.poll_raw(timeout);
+            if rc >= 0 {
+                break rc;
+            }
+            let err = io::Error::last_os_error();
+            if err.kind() != io::ErrorKind::Interrupted {
+                return Err(err);
+            }
+            // Interrupted by a signal handler: wait out the rest.
+            timeout = deadline.map(|d| d.saturating_duration_since(Instant::now()));
+        };
+        // Draining resets the mirror's ready list; level-triggered entries
+        // that are still ready are queued again. A failure only costs the
+        // host a spurious wakeup, so it must not lose the poll results.
+        #[cfg(target_os = "linux")]
+        if let Some(mirror) = self.mirror.get_mut() {
+            let _ = mirror.wait(Some(Duration::ZERO));
         }
 
         let mut out = Vec::new();
@@ -93,12 +212,18 @@ impl PollBackend {
             return Ok(out);
         }
 
-        for p in &self.fds {
+        let mut disarmed = Vec::new();
+        for (p, entry) in self.fds.iter_mut().zip(&self.entries) {
             if p.revents == 0 {
                 continue;
             }
+            if entry.oneshot {
+                // poll ignores negative fds, which disarms the entry until modify.
+                disarmed.push((p.fd, entry.token));
+                p.fd = -1;
+            }
             out.push((
-                p.fd as RawFd,
+                entry.token,
                 Ready {
                     readable: (p.revents & POLLIN) != 0,
                     writable: (p.revents & POLLOUT) != 0,
@@ -107,6 +232,56 @@ impl PollBackend {
                 },
             ));
         }
+        for (fd, token) in disarmed {
+            self.mirror_unset(fd, token);
+        }
         Ok(out)
     }
+
+    #[cfg(target_os = "linux")]
+    fn as_raw_fd(&self) -> Option<RawFd> {
+        let mut mirror = self.mirror.borrow_mut();
+        if mirror.is_none() {
+            let mut m = super::linux::EpollBackend::new().ok()?;
+            for (p, entry) in self.fds.iter().zip(&self.entries) {
+                // Disarmed oneshot entries are added back by `modify`.
+                if p.fd >= 0 {
+                    mirror_add(&mut m, p.fd, entry.token, events_interest(p.events));
+                }
+            }
+            *mirror = Some(m);
+        }
+        mirror.as_ref()?.as_raw_fd()
+    }
+}
+
+// Mirror errors are ignored: epoll rejects some fds poll accepts, such as
+// regular files, and those just do not wake the host.
+#[cfg(target_os = "linux")]
+fn mirror_add(
+    mirror: &mut super::linux::EpollBackend,
+    fd: RawFd,
+    token: Token,
+    interest: Interest,
+) {
+    if mirror.modify(fd, token, interest, IoMode::LEVEL).is_err() {
+        let _ = mirror.register(fd, token, interest, IoMode::LEVEL);
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn events_interest(events: c_short) -> Interest {
+    match (events & POLLIN != 0, events & POLLOUT != 0) {
+        (_, false) => Interest::Readable,
+        (false, true) => Interest::Writable,
+        (true, true) => Interest::ReadWrite,
+    }
+}
+
+fn interest_events(interest: Interest) -> c_short {
+    match interest {
+        Interest::Readable => POLLIN,
+        Interest::Writable => POLLOUT,
+        Interest::ReadWrite => POLLIN | POLLOUT,
+    }
 }
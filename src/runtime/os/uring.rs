@@ -0,0 +1,727 @@
+use std::collections::HashMap;
+use std::io;
+use std::os::raw::{c_int, c_long, c_uint, c_void};
+use std::os::unix::io::RawFd;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::runtime::{Interest, IoMode, Poller, Ready, Token};
+
+#[repr(C)]
+#[derive(Default)]
+struct SqringOffsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    flags: u32,
+    dropped: u32,
+    array: u32,
+    resv1: u32,
+    user_addr: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct CqringOffsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    overflow: u32,
+    cqes: u32,
+    flags: u32,
+    resv1: u32,
+    user_addr: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct IoUringParams {
+    sq_entries: u32,
+    cq_entries: u32,
+    flags: u32,
+    sq_thread_cpu: u32,
+    sq_thread_idle: u32,
+    features: u32,
+    wq_fd: u32,
+    resv: [u32; 3],
+    sq_off: SqringOffsets,
+    cq_off: CqringOffsets,
+}
+
+/// Submission queue entry. Union members of the kernel struct are flattened
+/// to the name of their first variant.
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub(crate) struct Sqe {
+    pub(crate) opcode: u8,
+    pub(crate) flags: u8,
+    pub(crate) ioprio: u16,
+    pub(crate) fd: i32,
+    pub(crate) off: u64,
+    pub(crate) addr: u64,
+    pub(crate) len: u32,
+    pub(crate) op_flags: u32,
+    pub(crate) user_data: u64,
+    pub(crate) buf_index: u16,
+    pub(crate) personality: u16,
+    pub(crate) splice_fd_in: i32,
+    pub(crate) addr3: u64,
+    pub(crate) pad: u64,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub(crate) struct Cqe {
+    pub(crate) user_data: u64,
+    pub(crate) res: i32,
+    pub(crate) flags: u32,
+}
+
+#[repr(C)]
+struct GeteventsArg {
+    sigmask: u64,
+    sigmask_sz: u32,
+    pad: u32,
+    ts: u64,
+}
+
+#[repr(C)]
+struct KernelTimespec {
+    tv_sec: i64,
+    tv_nsec: i64,
+}
+
+extern "C" {
+    fn syscall(num: c_long, ...) -> c_long;
+    fn mmap(
+        addr: *mut c_void,
+        len: usize,
+        prot: c_int,
+        flags: c_int,
+        fd: c_int,
+        off: i64,
+    ) -> *mut c_void;
+    fn munmap(addr: *mut c_void, len: usize) -> c_int;
+    fn close(fd: c_int) -> c_int;
+}
+
+// io_uring syscalls share these numbers on every architecture.
+const SYS_IO_URING_SETUP: c_long = 425;
+const SYS_IO_URING_ENTER: c_long = 426;
+
+const IORING_OFF_SQ_RING: i64 = 0;
+const IORING_OFF_CQ_RING: i64 = 0x8000000;
+const IORING_OFF_SQES: i64 = 0x10000000;
+
+const IORING_FEAT_SINGLE_MMAP: u32 = 1 << 0;
+const IORING_FEAT_EXT_ARG: u32 = 1 << 8;
+
+const IORING_ENTER_GETEVENTS: c_uint = 1 << 0;
+const IORING_ENTER_EXT_ARG: c_uint = 1 << 3;
+
+pub(crate) const IORING_OP_FSYNC: u8 = 3;
+pub(crate) const IORING_OP_POLL_ADD: u8 = 6;
+pub(crate) const IORING_OP_POLL_REMOVE: u8 = 7;
+pub(crate) const IORING_OP_ACCEPT: u8 = 13;
+pub(crate) const IORING_OP_ASYNC_CANCEL: u8 = 14;
+pub(crate) const IORING_OP_CONNECT: u8 = 16;
+pub(crate) const IORING_OP_OPENAT: u8 = 18;
+pub(crate) const IORING_OP_READ: u8 = 22;
+pub(crate) const IORING_OP_WRITE: u8 = 23;
+
+const IORING_POLL_ADD_MULTI: u32 = 1 << 0;
+pub(crate) const IORING_CQE_F_MORE: u32 = 1 << 1;
+
+const PROT_READ: c_int = 0x1;
+const PROT_WRITE: c_int = 0x2;
+const MAP_SHARED: c_int = 0x01;
+const MAP_POPULATE: c_int = 0x8000;
+const MAP_FAILED: *mut c_void = !0usize as *mut c_void;
+
+const EPERM: i32 = 1;
+const EINVAL: i32 = 22;
+const ENOSYS: i32 = 38;
+const ETIME: i32 = 62;
+const ECANCELED: i32 = 125;
+
+const POLLIN: u32 = 0x0001;
+const POLLOUT: u32 = 0x0004;
+const POLLERR: u32 = 0x0008;
+const POLLHUP: u32 = 0x0010;
+
+struct Mapping {
+    ptr: *mut c_void,
+    len: usize,
+}
+
+impl Mapping {
+    fn new(fd: RawFd, len: usize, offset: i64) -> io::Result<Self> {
+        let ptr = unsafe {
+            mmap(
+                std::ptr::null_mut(),
+                len,
+                PROT_READ | PROT_WRITE,
+                MAP_SHARED | MAP_POPULATE,
+                fd as c_int,
+                offset,
+            )
+        };
+        if ptr == MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self { ptr, len })
+    }
+
+    unsafe fn at<T>(&self, offset: u32) -> *mut T {
+        (self.ptr as *mut u8).add(offset as usize) as *mut T
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        unsafe {
+            let _ = munmap(self.ptr, self.len);
+        }
+    }
+}
+
+/// A raw io_uring instance driven through `io_uring_setup`/`io_uring_enter`.
+///
+/// Requires `IORING_FEAT_EXT_ARG` (Linux 5.11) so waits can carry a timeout
+/// without occupying a submission slot.
+pub(crate) struct Ring {
+    fd: RawFd,
+    sq_head: *const AtomicU32,
+    sq_tail: *const AtomicU32,
+    sq_mask: u32,
+    sq_entries: u32,
+    sq_array: *mut u32,
+    sqes: *mut Sqe,
+    cq_head: *const AtomicU32,
+    cq_tail: *const AtomicU32,
+    cq_mask: u32,
+    cqes: *const Cqe,
+    to_submit: u32,
+    // Unmapped after `Drop` closes the fd; the kernel frees the ring once
+    // both the fd and the mappings are gone, so the order does not matter.
+    _sq_map: Mapping,
+    _cq_map: Option<Mapping>,
+    _sqe_map: Mapping,
+}
+
+// The raw pointers only address the ring's own mappings.
+unsafe impl Send for Ring {}
+
+impl Ring {
+    pub(crate) fn new(entries: u32) -> io::Result<Self> {
+        let mut p = IoUringParams::default();
+        let rc = unsafe { syscall(SYS_IO_URING_SETUP, entries as c_long, &mut p) };
+        if rc < 0 {
+            let err = io::Error::last_os_error();
+            return Err(match err.raw_os_error() {
+                Some(ENOSYS) | Some(EPERM) => io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    format!("io_uring is not available: {err}"),
+                ),
+                _ => err,
+            });
+        }
+        let fd = rc as RawFd;
+
+        if p.features & IORING_FEAT_EXT_ARG == 0 {
+            unsafe {
+                let _ = close(fd as c_int);
+            }
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "io_uring lacks IORING_FEAT_EXT_ARG (linux 5.11+ required)",
+            ));
+        }
+
+        match Self::map(fd, &p) {
+            Ok(ring) => Ok(ring),
+            Err(e) => {
+                unsafe {
+                    let _ = close(fd as c_int);
+                }
+                Err(e)
+            }
+        }
+    }
+
+    fn map(fd: RawFd, p: &IoUringParams) -> io::Result<Self> {
+        let sq_len = p.sq_off.array as usize + p.sq_entries as usize * 4;
+        let cq_len = p.cq_off.cqes as usize + p.cq_entries as usize * std::mem::size_of::<Cqe>();
+
+        let single = p.features & IORING_FEAT_SINGLE_MMAP != 0;
+        let sq_map = Mapping::new(
+            fd,
+            if single { sq_len.max(cq_len) } else { sq_len },
+            IORING_OFF_SQ_RING,
+        )?;
+        let cq_map = if single {
+            None
+        } else {
+            Some(Mapping::new(fd, cq_len, IORING_OFF_CQ_RING)?)
+        };
+        let sqe_map = Mapping::new(
+            fd,
+            p.sq_entries as usize * std::mem::size_of::<Sqe>(),
+            IORING_OFF_SQES,
+        )?;
+
+        unsafe {
+            let cq = cq_map.as_ref().unwrap_or(&sq_map);
+            Ok(Self {
+                fd,
+                sq_head: sq_map.at(p.sq_off.head),
+                sq_tail: sq_map.at(p.sq_off.tail),
+                sq_mask: *sq_map.at::<u32>(p.sq_off.ring_mask),
+                sq_entries: *sq_map.at::<u32>(p.sq_off.ring_entries),
+                sq_array: sq_map.at(p.sq_off.array),
+                sqes: sqe_map.ptr as *mut Sqe,
+                cq_head: cq.at(p.cq_off.head),
+                cq_tail: cq.at(p.cq_off.tail),
+                cq_mask: *cq.at::<u32>(p.cq_off.ring_mask),
+                cqes: cq.at(p.cq_off.cqes),
+                to_submit: 0,
+                _sq_map: sq_map,
+                _cq_map: cq_map,
+                _sqe_map: sqe_map,
+            })
+        }
+    }
+
+    pub(crate) fn fd(&self) -> RawFd {
+        self.fd
+    }
+
+    /// Queues `sqe`, flushing queued entries to the kernel first if the
+    /// submission ring is full.
+    pub(crate) fn push(&mut self, sqe: Sqe) -> io::Result<()> {
+        let (head, tail) = unsafe {
+            (
+                (*self.sq_head).load(Ordering::Acquire),
+                (*self.sq_tail).load(Ordering::Relaxed),
+            )
+        };
+        if tail.wrapping_sub(head) >= self.sq_entries {
+            self.submit()?;
+            return self.push(sqe);
+        }
+        let idx = tail & self.sq_mask;
+        unsafe {
+            *self.sqes.add(idx as usize) = sqe;
+            *self.sq_array.add(idx as usize) = idx;
+            (*self.sq_tail).store(tail.wrapping_add(1), Ordering::Release);
+        }
+        self.to_submit += 1;
+        Ok(())
+    }
+
+    pub(crate) fn submit(&mut self) -> io::Result<()> {
+        if self.to_submit == 0 {
+            return Ok(());
+        }
+        self.enter(0, None, 0)?;
+        Ok(())
+    }
+
+    /// Submits queued entries and waits for at least one completion or until
+    /// `timeout` passes. A zero timeout only submits.
+    pub(crate) fn submit_and_wait(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        if timeout == Some(Duration::ZERO) || self.cq_ready() {
+            return self.submit();
+        }
+        let ts = timeout.map(|d| KernelTimespec {
+            tv_sec: d.as_secs().min(i64::MAX as u64) as i64,
+            tv_nsec: d.subsec_nanos() as i64,
+        });
+        let arg = GeteventsArg {
+            sigmask: 0,
+            sigmask_sz: 8,
+            pad: 0,
+            ts: ts.as_ref().map_or(0, |t| t as *const KernelTimespec as u64),
+        };
+        match self.enter(1, Some(&arg), IORING_ENTER_GETEVENTS) {
+            Ok(()) => Ok(()),
+            Err(e) if e.raw_os_error() == Some(ETIME) => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn enter(
+        &mut self,
+        min_complete: u32,
+        arg: Option<&GeteventsArg>,
+        mut flags: c_uint,
+    ) -> io::Result<()> {
+        let (arg_ptr, arg_sz) = match arg {
+            Some(a) => {
+                flags |= IORING_ENTER_EXT_ARG;
+                (
+                    a as *const GeteventsArg as *const c_void,
+                    std::mem::size_of::<GeteventsArg>(),
+                )
+            }
+            None => (std::ptr::null(), 0),
+        };
+        let rc = unsafe {
+            syscall(
+                SYS_IO_URING_ENTER,
+                self.fd as c_long,
+                self.to_submit as c_long,
+                min_complete as c_long,
+                flags as c_long,
+                arg_ptr,
+                arg_sz,
+            )
+        };
+        if rc < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        self.to_submit -= (rc as u32).min(self.to_submit);
+        Ok(())
+    }
+
+    pub(crate) fn cq_ready(&self) -> bool {
+        unsafe {
+            (*self.cq_head).load(Ordering::Relaxed) != (*self.cq_tail).load(Ordering::Acquire)
+        }
+    }
+
+    pub(crate) fn pop_cqe(&mut self) -> Option<Cqe> {
+        unsafe {
+            let head = (*self.cq_head).load(Ordering::Relaxed);
+            if head == (*self.cq_tail).load(Ordering::Acquire) {
+                return None;
+            }
+            let cqe = *self.cqes.add((head & self.cq_mask) as usize);
+            (*self.cq_head).store(head.wrapping_add(1), Ordering::Release);
+            Some(cqe)
+        }
+    }
+}
+
+impl Drop for Ring {
+    fn drop(&mut self) {
+        unsafe {
+            let _ = close(self.fd as c_int);
+        }
+    }
+}
+
+struct PollEntry {
+    fd: RawFd,
+    events: u32,
+    mode: IoMode,
+    user_data: u64,
+    armed: bool,
+}
+
+// user_data 0 tags internal requests (poll removals) whose completions are
+// ignored.
+const INTERNAL_USER_DATA: u64 = 0;
+
+/// Readiness backend built on `IORING_OP_POLL_ADD`.
+///
+/// Level-triggered registrations use single-shot polls that are re-armed at
+/// the start of the next `wait`, after callbacks had a chance to consume the
+/// readiness. Edge-triggered ones use multishot polls where the kernel
+/// supports them (5.13) and fall back to level behaviour otherwise.
+/// `IoMode::exclusive` is ignored.
+pub struct IoUringBackend {
+    ring: Ring,
+    entries: HashMap<Token, PollEntry>,
+    by_user_data: HashMap<u64, Token>,
+    next_user_data: u64,
+    rearm: Vec<Token>,
+    multishot: bool,
+}
+
+impl IoUringBackend {
+    pub fn new() -> io::Result<Self> {
+        Ok(Self {
+            ring: Ring::new(1024)?,
+            entries: HashMap::new(),
+            by_user_data: HashMap::new(),
+            next_user_data: INTERNAL_USER_DATA + 1,
+            rearm: Vec::new(),
+            multishot: true,
+        })
+    }
+
+    fn arm(&mut self, token: Token) -> io::Result<()> {
+        let Some(entry) = self.entries.get_mut(&token) else {
+            return Ok(());
+        };
+        if entry.armed {
+            return Ok(());
+        }
+        let user_data = self.next_user_data;
+        self.next_user_data += 1;
+        entry.user_data = user_data;
+        entry.armed = true;
+
+        let multishot = self.multishot && entry.mode.edge_triggered && !entry.mode.oneshot;
+        let sqe = Sqe {
+            opcode: IORING_OP_POLL_ADD,
+            fd: entry.fd,
+            len: if multishot { IORING_POLL_ADD_MULTI } else { 0 },
+            op_flags: entry.events,
+            user_data,
+            ..Sqe::default()
+        };
+        self.by_user_data.insert(user_data, token);
+        self.ring.push(sqe)
+    }
+
+    fn cancel(&mut self, token: Token) -> io::Result<()> {
+        let Some(entry) = self.entries.get_mut(&token) else {
+            return Ok(());
+        };
+        self.by_user_data.remove(&entry.user_data);
+        if !entry.armed {
+            return Ok(());
+        }
+        entry.armed = false;
+        let sqe = Sqe {
+            opcode: IORING_OP_POLL_REMOVE,
+            addr: entry.user_data,
+            user_data: INTERNAL_USER_DATA,
+            ..Sqe::default()
+        };
+        self.ring.push(sqe)
+    }
+}
+
+impl Poller for IoUringBackend {
+    fn register(
+        &mut self,
+        fd: RawFd,
+        token: Token,
+        interest: Interest,
+        mode: IoMode,
+    ) -> io::Result<()> {
+        if self.entries.contains_key(&token) {
+            return Ok(());
+        }
+        self.entries.insert(
+            token,
+            PollEntry {
+                fd,
+                events: interest_events(interest),
+                mode,
+                user_data: 0,
+                armed: false,
+            },
+        );
+        self.arm(token)
+    }
+
+    fn modify(
+        &mut self,
+        _fd: RawFd,
+        token: Token,
+        interest: Interest,
+        mode: IoMode,
+    ) -> io::Result<()> {
+        let Some(entry) = self.entries.get_mut(&token) else {
+            return Err(io::Error::from(io::ErrorKind::NotFound));
+        };
+        entry.events = interest_events(interest);
+        entry.mode = mode;
+        self.cancel(token)?;
+        self.arm(token)
+    }
+
+    fn deregister(&mut self, _fd: RawFd, token: Token) -> io::Result<()> {
+        self.cancel(token)?;
+        self.entries.remove(&token);
+        Ok(())
+    }
+
+    fn wait(&mut self, mut timeout: Option<Duration>) -> io::Result<Vec<(Token, Ready)>> {
+        for token in std::mem::take(&mut self.rearm) {
+            self.arm(token)?;
+        }
+
+        let deadline = timeout.and_then(|d| Instant::now().checked_add(d));
+        loop {
+            match self.ring.submit_and_wait(timeout) {
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                res => res?,
+            }
+            // A wait interrupted by a signal handler fails with EINTR, or
+            // returns early without completions if it submitted anything.
+            if self.ring.cq_ready() || timeout == Some(Duration::ZERO) {
+                break;
+            }
+            timeout = deadline.map(|d| d.saturating_duration_since(Instant::now()));
+        }
+
+        let mut out = Vec::new();
+        while let Some(cqe) = self.ring.pop_cqe() {
+            if cqe.user_data == INTERNAL_USER_DATA {
+                continue;
+            }
+            let Some(&token) = self.by_user_data.get(&cqe.user_data) else {
+                continue;
+            };
+            let Some(entry) = self.entries.get_mut(&token) else {
+                continue;
+            };
+
+            if cqe.flags & IORING_CQE_F_MORE == 0 {
+                entry.armed = false;
+                self.by_user_data.remove(&cqe.user_data);
+            }
+
+            if cqe.res < 0 {
+                match -cqe.res {
+                    ECANCELED => {}
+                    EINVAL if self.multishot && entry.mode.edge_triggered => {
+                        self.multishot = false;
+                        self.rearm.push(token);
+                    }
+                    _ => out.push((
+                        token,
+                        Ready {
+                            error: true,
+                            ..Ready::default()
+                        },
+                    )),
+                }
+                continue;
+            }
+
+            let bits = cqe.res as u32;
+            if !entry.armed && !entry.mode.oneshot {
+                self.rearm.push(token);
+            }
+            out.push((
+                token,
+                Ready {
+                    readable: (bits & POLLIN) != 0,
+                    writable: (bits & POLLOUT) != 0,
+                    error: (bits & POLLERR) != 0,
+                    hup: (bits & POLLHUP) != 0,
+                },
+            ));
+        }
+        Ok(out)
+    }
+}
+
+fn interest_events(interest: Interest) -> u32 {
+    match interest {
+        Interest::Readable => POLLIN,
+        Interest::Writable => POLLOUT,
+        Interest::ReadWrite => POLLIN | POLLOUT,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::os::unix::io::AsRawFd;
+    use std::os::unix::net::UnixStream;
+
+    // io_uring is often disabled in containers; those runs skip the test.
+    fn backend() -> Option<IoUringBackend> {
+        match IoUringBackend::new() {
+            Ok(backend) => Some(backend),
+            Err(e) if e.kind() == io::ErrorKind::Unsupported => None,
+            Err(e) => panic!("io_uring setup failed: {e}"),
+        }
+    }
+
+    fn readable(events: &[(Token, Ready)]) -> bool {
+        events
+            .iter()
+            .any(|&(t, r)| t == Token::new(1, 0) && r.readable)
+    }
+
+    #[test]
+    fn level_registration_reports_until_drained() {
+        let Some(mut backend) = backend() else { return };
+        let (mut a, mut b) = UnixStream::pair().unwrap();
+        a.set_nonblocking(true).unwrap();
+        backend
+            .register(
+                a.as_raw_fd(),
+                Token::new(1, 0),
+                Interest::Readable,
+                IoMode::LEVEL,
+            )
+            .unwrap();
+
+        b.write_all(b"ping").unwrap();
+        let wait = Some(Duration::from_secs(1));
+        assert!(readable(&backend.wait(wait).unwrap()));
+        assert!(readable(&backend.wait(wait).unwrap()));
+
+        let mut buf = [0; 8];
+        assert_eq!(a.read(&mut buf).unwrap(), 4);
+        assert!(!readable(
+            &backend.wait(Some(Duration::from_millis(20))).unwrap()
+        ));
+    }
+
+    #[test]
+    fn oneshot_stays_quiet_until_modified() {
+        let Some(mut backend) = backend() else { return };
+        let (a, mut b) = UnixStream::pair().unwrap();
+        backend
+            .register(
+                a.as_raw_fd(),
+                Token::new(1, 0),
+                Interest::Readable,
+                IoMode::ONESHOT,
+            )
+            .unwrap();
+
+        b.write_all(b"ping").unwrap();
+        assert!(readable(
+            &backend.wait(Some(Duration::from_secs(1))).unwrap()
+        ));
+        assert!(!readable(
+            &backend.wait(Some(Duration::from_millis(20))).unwrap()
+        ));
+
+        backend
+            .modify(
+                a.as_raw_fd(),
+                Token::new(1, 0),
+                Interest::Readable,
+                IoMode::ONESHOT,
+            )
+            .unwrap();
+        assert!(readable(
+            &backend.wait(Some(Duration::from_secs(1))).unwrap()
+        ));
+    }
+
+    #[test]
+    fn deregistered_fd_reports_nothing() {
+        let Some(mut backend) = backend() else { return };
+        let (a, mut b) = UnixStream::pair().unwrap();
+        backend
+            .register(
+                a.as_raw_fd(),
+                Token::new(1, 0),
+                Interest::Readable,
+                IoMode::LEVEL,
+            )
+            .unwrap();
+        backend.deregister(a.as_raw_fd(), Token::new(1, 0)).unwrap();
+
+        b.write_all(b"ping").unwrap();
+        assert!(!readable(
+            &backend.wait(Some(Duration::from_millis(20))).unwrap()
+        ));
+    }
+}
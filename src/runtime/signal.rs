@@ -0,0 +1,379 @@
+use super::slab::Slab;
+use super::Token;
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read};
+use std::os::raw::c_int;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+
+/// Identifies a signal handler registered with `EventLoop::add_signal`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SignalId(pub(crate) Token);
+
+pub(crate) type SignalCallback = Box<dyn FnMut(&mut super::EventLoop) + 'static>;
+
+pub(crate) struct SignalHandler {
+    pub(crate) signo: i32,
+    pub(crate) callback: Option<SignalCallback>,
+}
+
+/// Per-loop signal state, created on the first `add_signal`.
+pub(crate) struct Signals {
+    pub(crate) source: SignalSource,
+    pub(crate) handlers: Slab<SignalHandler>,
+    pub(crate) by_signo: HashMap<i32, Vec<Token>>,
+}
+
+impl Drop for Signals {
+    fn drop(&mut self) {
+        for &signo in self.by_signo.keys() {
+            let _ = self.source.remove(signo);
+        }
+    }
+}
+
+/// Readable fd that yields delivered signal numbers.
+///
+/// On Linux this is a `signalfd`; the watched signals are blocked on the
+/// thread that adds them, which must be the loop thread. Elsewhere a
+/// process-wide handler writes the signal number into a pipe, so only one
+/// loop per process can watch signals.
+pub(crate) struct SignalSource {
+    file: File,
+    #[cfg(not(target_os = "linux"))]
+    _writer: File,
+    #[cfg(target_os = "linux")]
+    mask: SigSet,
+    // Watched signals that the thread had blocked before `add`; `remove`
+    // leaves those blocked.
+    #[cfg(target_os = "linux")]
+    preblocked: SigSet,
+}
+
+impl SignalSource {
+    pub(crate) fn fd(&self) -> RawFd {
+        self.file.as_raw_fd()
+    }
+
+    /// Drains the fd and returns the delivered signal numbers in order.
+    pub(crate) fn read(&mut self) -> Vec<i32> {
+        let mut out = Vec::new();
+        let mut buf = [0u8; RECORD_SIZE * 8];
+        loop {
+            match self.file.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => out.extend(buf[..n].chunks_exact(RECORD_SIZE).map(record_signo)),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(_) => break,
+            }
+        }
+        out
+    }
+}
+
+fn check_signo(signo: i32) -> io::Result<()> {
+    if signo <= 0 || signo >= 65 || signo == SIGKILL || signo == SIGSTOP {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("signal {signo} cannot be handled"),
+        ));
+    }
+    Ok(())
+}
+
+const SIGKILL: i32 = 9;
+#[cfg(target_os = "linux")]
+const SIGSTOP: i32 = 19;
+#[cfg(not(target_os = "linux"))]
+const SIGSTOP: i32 = 17;
+
+// `struct signalfd_siginfo`; `ssi_signo` is its first field.
+#[cfg(target_os = "linux")]
+const RECORD_SIZE: usize = 128;
+#[cfg(not(target_os = "linux"))]
+const RECORD_SIZE: usize = 1;
+
+#[cfg(target_os = "linux")]
+fn record_signo(record: &[u8]) -> i32 {
+    u32::from_ne_bytes([record[0], record[1], record[2], record[3]]) as i32
+}
+
+#[cfg(not(target_os = "linux"))]
+fn record_signo(record: &[u8]) -> i32 {
+    record[0] as i32
+}
+
+#[cfg(target_os = "linux")]
+mod imp {
+    use super::*;
+    use std::os::raw::{c_long, c_void};
+
+    // glibc's sigset_t; the kernel only reads the first 8 bytes.
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    pub(super) struct SigSet([u64; 16]);
+
+    extern "C" {
+        fn sigemptyset(set: *mut SigSet) -> c_int;
+        fn sigaddset(set: *mut SigSet, signo: c_int) -> c_int;
+        fn sigdelset(set: *mut SigSet, signo: c_int) -> c_int;
+        fn sigismember(set: *const SigSet, signo: c_int) -> c_int;
+        fn sigtimedwait(set: *const SigSet, info: *mut c_void, timeout: *const Timespec) -> c_int;
+        fn pthread_sigmask(how: c_int, set: *const SigSet, old: *mut SigSet) -> c_int;
+        fn signalfd(fd: c_int, mask: *const SigSet, flags: c_int) -> c_int;
+    }
+
+    const SIG_BLOCK: c_int = 0;
+    const SIG_UNBLOCK: c_int = 1;
+    const SFD_NONBLOCK: c_int = 0o4000;
+    const SFD_CLOEXEC: c_int = 0o2000000;
+
+    fn single(signo: i32) -> SigSet {
+        let mut set = SigSet([0; 16]);
+        unsafe {
+            sigemptyset(&mut set);
+            sigaddset(&mut set, signo as c_int);
+        }
+        set
+    }
+
+    #[repr(C)]
+    struct Timespec {
+        tv_sec: c_long,
+        tv_nsec: c_long,
+    }
+
+    /// Changes whether `signo` is blocked and returns whether it was.
+    fn set_blocked(signo: i32, blocked: bool) -> io::Result<bool> {
+        let how = if blocked { SIG_BLOCK } else { SIG_UNBLOCK };
+        let mut old = SigSet([0; 16]);
+        let rc = unsafe { pthread_sigmask(how, &single(signo), &mut old) };
+        if rc != 0 {
+            return Err(io::Error::from_raw_os_error(rc));
+        }
+        Ok(unsafe { sigismember(&old, signo as c_int) } == 1)
+    }
+
+    /// Discards pending instances of `signo`, which would otherwise take
+    /// their default action once unblocked.
+    fn discard_pending(signo: i32) {
+        let set = single(signo);
+        let zero = Timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        };
+        while unsafe { sigtimedwait(&set, std::ptr::null_mut(), &zero) } == signo {}
+    }
+
+    impl SignalSource {
+        pub(crate) fn new() -> io::Result<Self> {
+            let mut mask = SigSet([0; 16]);
+            unsafe { sigemptyset(&mut mask) };
+            let fd = unsafe { signalfd(-1, &mask, SFD_NONBLOCK | SFD_CLOEXEC) };
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(Self {
+                file: unsafe { File::from_raw_fd(fd) },
+                mask,
+                preblocked: mask,
+            })
+        }
+
+        pub(crate) fn add(&mut self, signo: i32) -> io::Result<()> {
+            check_signo(signo)?;
+            let mut mask = self.mask;
+            unsafe { sigaddset(&mut mask, signo as c_int) };
+            // Block first so a signal arriving in between stays pending
+            // instead of taking its default action.
+            let was_blocked = set_blocked(signo, true)?;
+            if let Err(e) = self.update(mask) {
+                if !was_blocked {
+                    let _ = set_blocked(signo, false);
+                }
+                return Err(e);
+            }
+            if was_blocked {
+                unsafe { sigaddset(&mut self.preblocked, signo as c_int) };
+            }
+            Ok(())
+        }
+
+        /// Stops watching `signo` and restores the blocked state it had
+        /// before `add`. Instances still pending are discarded rather than
+        /// left to their default action.
+        pub(crate) fn remove(&mut self, signo: i32) -> io::Result<()> {
+            let mut mask = self.mask;
+            unsafe { sigdelset(&mut mask, signo as c_int) };
+            self.update(mask)?;
+            if unsafe { sigismember(&self.preblocked, signo as c_int) } == 1 {
+                unsafe { sigdelset(&mut self.preblocked, signo as c_int) };
+                return Ok(());
+            }
+            discard_pending(signo);
+            set_blocked(signo, false).map(|_| ())
+        }
+
+        fn update(&mut self, mask: SigSet) -> io::Result<()> {
+            let rc = unsafe { signalfd(self.file.as_raw_fd(), &mask, 0) };
+            if rc < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            self.mask = mask;
+            Ok(())
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        extern "C" {
+            fn raise(signo: c_int) -> c_int;
+        }
+
+        const SIGUSR1: i32 = 10;
+        const SIGUSR2: i32 = 12;
+        const SIGALRM: i32 = 14;
+
+        // Signal masks are per thread and each test runs on its own thread.
+        fn is_blocked(signo: i32) -> bool {
+            let mut set = SigSet([0; 16]);
+            unsafe { pthread_sigmask(SIG_BLOCK, std::ptr::null(), &mut set) };
+            unsafe { sigismember(&set, signo as c_int) == 1 }
+        }
+
+        #[test]
+        fn remove_unblocks_what_add_blocked() {
+            let mut source = SignalSource::new().unwrap();
+            assert!(!is_blocked(SIGUSR1));
+            source.add(SIGUSR1).unwrap();
+            assert!(is_blocked(SIGUSR1));
+            source.remove(SIGUSR1).unwrap();
+            assert!(!is_blocked(SIGUSR1));
+        }
+
+        #[test]
+        fn remove_keeps_a_signal_blocked_before_add() {
+            let mut source = SignalSource::new().unwrap();
+            assert!(!set_blocked(SIGUSR2, true).unwrap());
+            source.add(SIGUSR2).unwrap();
+            source.remove(SIGUSR2).unwrap();
+            assert!(is_blocked(SIGUSR2));
+            set_blocked(SIGUSR2, false).unwrap();
+        }
+
+        #[test]
+        fn delivered_signals_are_read_and_leftovers_discarded() {
+            let mut source = SignalSource::new().unwrap();
+            source.add(SIGALRM).unwrap();
+            unsafe { raise(SIGALRM) };
+            assert_eq!(source.read(), [SIGALRM]);
+
+            // Still pending on removal; unblocking must not deliver it.
+            unsafe { raise(SIGALRM) };
+            source.remove(SIGALRM).unwrap();
+            assert!(!is_blocked(SIGALRM));
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+use imp::SigSet;
+
+#[cfg(not(target_os = "linux"))]
+mod imp {
+    use super::*;
+    use std::os::raw::c_void;
+    use std::sync::atomic::{AtomicI32, Ordering};
+
+    extern "C" {
+        fn pipe(fds: *mut c_int) -> c_int;
+        fn write(fd: c_int, buf: *const c_void, len: usize) -> isize;
+        fn signal(signo: c_int, handler: usize) -> usize;
+        fn fcntl(fd: c_int, cmd: c_int, ...) -> c_int;
+    }
+
+    const SIG_DFL: usize = 0;
+    const SIG_ERR: usize = !0;
+    const F_GETFL: c_int = 3;
+    const F_SETFL: c_int = 4;
+    const O_NONBLOCK: c_int = 0x0004;
+
+    static PIPE_WRITER: AtomicI32 = AtomicI32::new(-1);
+
+    extern "C" fn on_signal(signo: c_int) {
+        let fd = PIPE_WRITER.load(Ordering::Relaxed);
+        if fd >= 0 {
+            let b = signo as u8;
+            unsafe {
+                let _ = write(fd, &b as *const u8 as *const c_void, 1);
+            }
+        }
+    }
+
+    fn set_nonblocking(fd: c_int) -> io::Result<()> {
+        unsafe {
+            let flags = fcntl(fd, F_GETFL);
+            if flags < 0 || fcntl(fd, F_SETFL, flags | O_NONBLOCK) < 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+
+    impl SignalSource {
+        pub(crate) fn new() -> io::Result<Self> {
+            let mut fds = [0 as c_int; 2];
+            if unsafe { pipe(fds.as_mut_ptr()) } < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let file = unsafe { File::from_raw_fd(fds[0]) };
+            let writer = unsafe { File::from_raw_fd(fds[1]) };
+            set_nonblocking(fds[0])?;
+            // The handler must never block inside a signal context.
+            set_nonblocking(fds[1])?;
+            if PIPE_WRITER
+                .compare_exchange(-1, fds[1], Ordering::SeqCst, Ordering::SeqCst)
+                .is_err()
+            {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    "signals are already handled by another event loop",
+                ));
+            }
+            Ok(Self {
+                file,
+                _writer: writer,
+            })
+        }
+
+        pub(crate) fn add(&mut self, signo: i32) -> io::Result<()> {
+            check_signo(signo)?;
+            if unsafe { signal(signo as c_int, on_signal as usize) } == SIG_ERR {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        }
+
+        /// Stops watching `signo` and restores its default disposition.
+        pub(crate) fn remove(&mut self, signo: i32) -> io::Result<()> {
+            if unsafe { signal(signo as c_int, SIG_DFL) } == SIG_ERR {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        }
+    }
+
+    impl Drop for SignalSource {
+        fn drop(&mut self) {
+            let _ = PIPE_WRITER.compare_exchange(
+                self._writer.as_raw_fd(),
+                -1,
+                Ordering::SeqCst,
+                Ordering::SeqCst,
+            );
+        }
+    }
+}
@@ -0,0 +1,106 @@
+use super::Token;
+
+pub(crate) struct Slab<T> {
+    entries: Vec<Entry<T>>,
+    free: Vec<u32>,
+    len: usize,
+}
+
+struct Entry<T> {
+    generation: u32,
+    value: Option<T>,
+}
+
+impl<T> Slab<T> {
+    pub(crate) fn new() -> Self {
+        Self {
+            entries: Vec::new(),
+            free: Vec::new(),
+            len: 0,
+        }
+    }
+
+    pub(crate) fn insert(&mut self, value: T) -> Token {
+        self.len += 1;
+        if let Some(index) = self.free.pop() {
+            let entry = &mut self.entries[index as usize];
+            entry.value = Some(value);
+            return Token::new(index, entry.generation);
+        }
+        let index = self.entries.len() as u32;
+        self.entries.push(Entry {
+            generation: 0,
+            value: Some(value),
+        });
+        Token::new(index, 0)
+    }
+
+    pub(crate) fn get(&self, token: Token) -> Option<&T> {
+        let entry = self.entries.get(token.index() as usize)?;
+        if entry.generation != token.generation() {
+            return None;
+        }
+        entry.value.as_ref()
+    }
+
+    pub(crate) fn get_mut(&mut self, token: Token) -> Option<&mut T> {
+        let entry = self.entries.get_mut(token.index() as usize)?;
+        if entry.generation != token.generation() {
+            return None;
+        }
+        entry.value.as_mut()
+    }
+
+    pub(crate) fn remove(&mut self, token: Token) -> Option<T> {
+        let entry = self.entries.get_mut(token.index() as usize)?;
+        if entry.generation != token.generation() {
+            return None;
+        }
+        let value = entry.value.take()?;
+        // A slot whose generation is exhausted is retired rather than
+        // wrapped, so no token is ever handed out twice.
+        if let Some(generation) = entry.generation.checked_add(1) {
+            entry.generation = generation;
+            self.free.push(token.index());
+        }
+        self.len -= 1;
+        Some(value)
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stale_token_does_not_match_a_reused_slot() {
+        let mut slab = Slab::new();
+        let old = slab.insert("old");
+        assert_eq!(slab.remove(old), Some("old"));
+
+        let new = slab.insert("new");
+        assert_eq!(new.index(), old.index());
+        assert_ne!(new, old);
+        assert_eq!(slab.get(old), None);
+        assert_eq!(slab.remove(old), None);
+        assert_eq!(slab.get(new), Some(&"new"));
+        assert_eq!(slab.len(), 1);
+    }
+
+    #[test]
+    fn exhausted_slot_is_retired() {
+        let mut slab = Slab::new();
+        let token = slab.insert(1);
+        slab.entries[token.index() as usize].generation = u32::MAX;
+        let last = Token::new(token.index(), u32::MAX);
+
+        assert_eq!(slab.remove(last), Some(1));
+        let next = slab.insert(2);
+        assert_ne!(next.index(), last.index());
+        assert_eq!(slab.get(last), None);
+    }
+}
@@ -0,0 +1,152 @@
+use super::{take_expired, Expired, Timer, TimerId, TimerTask};
+use crate::runtime::slab::Slab;
+
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::time::Instant;
+
+struct HeapEntry {
+    when: Instant,
+    seq: u64,
+    id: TimerId,
+}
+
+impl PartialEq for HeapEntry {
+    fn eq(&self, other: &Self) -> bool {
+        self.when == other.when && self.seq == other.seq
+    }
+}
+
+impl Eq for HeapEntry {}
+
+impl PartialOrd for HeapEntry {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for HeapEntry {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.when
+            .cmp(&other.when)
+            .then_with(|| self.seq.cmp(&other.seq))
+    }
+}
+
+/// Binary-heap timer store with lazy deletion: cancelled or reset timers
+/// leave stale heap entries behind that are skipped when they surface.
+pub(crate) struct TimerHeap {
+    timers: Slab<Timer>,
+    heap: BinaryHeap<Reverse<HeapEntry>>,
+    seq: u64,
+}
+
+impl TimerHeap {
+    pub(crate) fn new() -> Self {
+        Self {
+            timers: Slab::new(),
+            heap: BinaryHeap::new(),
+            seq: 0,
+        }
+    }
+
+    pub(crate) fn insert(&mut self, when: Instant, task: TimerTask) -> (TimerId, Arc<AtomicBool>) {
+        let cancelled = Arc::new(AtomicBool::new(false));
+        (self.insert_with(when, task, cancelled.clone()), cancelled)
+    }
+
+    pub(crate) fn insert_with(
+        &mut self,
+        when: Instant,
+        task: TimerTask,
+        cancelled: Arc<AtomicBool>,
+    ) -> TimerId {
+        let seq = self.next_seq();
+        let id = TimerId(self.timers.insert(Timer {
+            when,
+            seq,
+            task: Some(task),
+            cancelled,
+        }));
+        self.heap.push(Reverse(HeapEntry { when, seq, id }));
+        id
+    }
+
+    pub(crate) fn cancel(&mut self, id: TimerId) -> bool {
+        let removed = self.timers.remove(id.0).is_some();
+        self.compact();
+        removed
+    }
+
+    pub(crate) fn reset(&mut self, id: TimerId, when: Instant) -> bool {
+        let seq = self.next_seq();
+        let Some(t) = self.timers.get_mut(id.0) else {
+            return false;
+        };
+        t.when = when;
+        t.seq = seq;
+        self.heap.push(Reverse(HeapEntry { when, seq, id }));
+        self.compact();
+        true
+    }
+
+    pub(crate) fn next_deadline(&mut self) -> Option<Instant> {
+        self.skip_stale();
+        self.heap.peek().map(|Reverse(e)| e.when)
+    }
+
+    pub(crate) fn pop_expired(&mut self, now: Instant) -> Option<Expired> {
+        loop {
+            self.skip_stale();
+            let Reverse(e) = self.heap.peek()?;
+            if e.when > now {
+                return None;
+            }
+            let Reverse(e) = self.heap.pop().unwrap();
+            if let Some(expired) = take_expired(&mut self.timers, e.id) {
+                return Some(expired);
+            }
+        }
+    }
+
+    pub(crate) fn timers_mut(&mut self) -> &mut Slab<Timer> {
+        &mut self.timers
+    }
+
+    fn next_seq(&mut self) -> u64 {
+        let seq = self.seq;
+        self.seq = self.seq.wrapping_add(1);
+        seq
+    }
+
+    fn is_live(&self, e: &HeapEntry) -> bool {
+        matches!(self.timers.get(e.id.0), Some(t) if t.seq == e.seq && !t.is_cancelled())
+    }
+
+    fn skip_stale(&mut self) {
+        while let Some(Reverse(e)) = self.heap.peek() {
+            if self.is_live(e) {
+                return;
+            }
+            let Reverse(e) = self.heap.pop().unwrap();
+            if matches!(self.timers.get(e.id.0), Some(t) if t.seq == e.seq) {
+                self.timers.remove(e.id.0);
+            }
+        }
+    }
+
+    // Frequently reset idle timeouts would otherwise grow the heap without
+    // bound, since their stale entries sit far from the top.
+    fn compact(&mut self) {
+        if self.heap.len() <= 64 || self.heap.len() <= self.timers.len() * 2 {
+            return;
+        }
+        let heap = std::mem::take(&mut self.heap);
+        self.heap = heap
+            .into_iter()
+            .filter(|Reverse(e)| matches!(self.timers.get(e.id.0), Some(t) if t.seq == e.seq))
+            .collect();
+    }
+}
@@ -0,0 +1,389 @@
+mod heap;
+mod wheel;
+
+use super::slab::Slab;
+use super::{Handle, MissedTickBehavior, RepeatingTask, Task, TimerKind, Token};
+
+use std::io;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock, PoisonError};
+use std::time::{Duration, Instant};
+
+/// Identifies a pending timer on its `EventLoop`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TimerId(pub(crate) Token);
+
+/// Cancels or reschedules a timer from any thread.
+///
+/// Dropping the handle leaves the timer scheduled.
+#[derive(Clone)]
+pub struct TimerHandle {
+    id: TimerId,
+    cancelled: Arc<AtomicBool>,
+    handle: Handle,
+}
+
+impl TimerHandle {
+    pub(crate) fn new(id: TimerId, cancelled: Arc<AtomicBool>, handle: Handle) -> Self {
+        Self {
+            id,
+            cancelled,
+            handle,
+        }
+    }
+
+    pub fn id(&self) -> TimerId {
+        self.id
+    }
+
+    /// Prevents the timer from firing and removes it from the loop's timer
+    /// store. Has no effect on a timer whose callback is already running.
+    pub fn cancel(&self) -> io::Result<()> {
+        if self.cancelled.swap(true, Ordering::AcqRel) {
+            return Ok(());
+        }
+        let id = self.id;
+        self.handle.post_unbounded(move |loop_ref| {
+            loop_ref.cancel_timer(id);
+        })
+    }
+
+    /// Moves the deadline of a still-pending timer to `when`.
+    pub fn reset(&self, when: Instant) -> io::Result<()> {
+        let id = self.id;
+        self.handle.post_unbounded(move |loop_ref| {
+            loop_ref.reset_timer(id, when);
+        })
+    }
+}
+
+/// Cancels a timer scheduled with `Handle::post_delayed` or
+/// `Handle::post_at`, from any thread.
+///
+/// Dropping the handle leaves the timer scheduled.
+#[derive(Clone)]
+pub struct RemoteTimerHandle {
+    cancelled: Arc<AtomicBool>,
+    id: Arc<OnceLock<TimerId>>,
+}
+
+impl RemoteTimerHandle {
+    /// The timer's id once the loop has taken it into its timer store.
+    pub fn id(&self) -> Option<TimerId> {
+        self.id.get().copied()
+    }
+
+    /// Prevents the timer from firing. The loop discards it at its deadline
+    /// at the latest. Has no effect on a timer whose callback is already
+    /// running.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Release);
+    }
+}
+
+pub(crate) struct RemoteTimer {
+    pub(crate) when: Instant,
+    pub(crate) task: Task,
+    pub(crate) cancelled: Arc<AtomicBool>,
+    pub(crate) id: Arc<OnceLock<TimerId>>,
+}
+
+/// Timers scheduled from other threads, waiting for the loop to move them
+/// into its timer store.
+pub(crate) struct RemoteTimers {
+    epoch: Instant,
+    pending: Mutex<Vec<RemoteTimer>>,
+    has_pending: AtomicBool,
+    // Deadline of the poller wait in progress as nanoseconds since `epoch`,
+    // `u64::MAX` without one, or 0 while the loop is not waiting.
+    sleep_until: AtomicU64,
+}
+
+impl RemoteTimers {
+    pub(crate) fn new() -> Self {
+        Self {
+            epoch: Instant::now(),
+            pending: Mutex::new(Vec::new()),
+            has_pending: AtomicBool::new(false),
+            sleep_until: AtomicU64::new(0),
+        }
+    }
+
+    /// Queues a timer. Returns `true` if the loop has to be woken to fire it
+    /// in time, i.e. unless it already sleeps until no later than `when`.
+    pub(crate) fn push(&self, when: Instant, task: Task) -> (RemoteTimerHandle, bool) {
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let id = Arc::new(OnceLock::new());
+        self.pending
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(RemoteTimer {
+                when,
+                task,
+                cancelled: cancelled.clone(),
+                id: id.clone(),
+            });
+        self.has_pending.store(true, Ordering::SeqCst);
+        let sleep_until = self.sleep_until.load(Ordering::SeqCst);
+        let wake = sleep_until == 0 || self.nanos(when) < sleep_until;
+        (RemoteTimerHandle { cancelled, id }, wake)
+    }
+
+    pub(crate) fn take(&self) -> Vec<RemoteTimer> {
+        if !self.has_pending.swap(false, Ordering::SeqCst) {
+            return Vec::new();
+        }
+        std::mem::take(&mut *self.pending.lock().unwrap_or_else(PoisonError::into_inner))
+    }
+
+    /// Announces a poller wait of at most `timeout`. Returns `false`, and
+    /// stays awake, if timers arrived since the last `take`: the caller
+    /// takes them and recomputes the timeout. Either `push` sees the
+    /// deadline or this sees the timer.
+    pub(crate) fn sleep(&self, timeout: Option<Duration>) -> bool {
+        let until = match timeout {
+            Some(t) => Instant::now()
+                .checked_add(t)
+                .map_or(u64::MAX - 1, |t| self.nanos(t).clamp(1, u64::MAX - 1)),
+            None => u64::MAX,
+        };
+        self.sleep_until.store(until, Ordering::SeqCst);
+        if self.has_pending.load(Ordering::SeqCst) {
+            self.awake();
+            return false;
+        }
+        true
+    }
+
+    pub(crate) fn awake(&self) {
+        self.sleep_until.store(0, Ordering::SeqCst);
+    }
+
+    fn nanos(&self, t: Instant) -> u64 {
+        let n = t.saturating_duration_since(self.epoch).as_nanos();
+        n.min(u64::MAX as u128) as u64
+    }
+}
+
+pub(crate) enum TimerTask {
+    Once(Task),
+    Repeating {
+        period: Duration,
+        behavior: MissedTickBehavior,
+        f: RepeatingTask,
+    },
+}
+
+pub(crate) struct Timer {
+    pub(crate) when: Instant,
+    pub(crate) seq: u64,
+    // `None` while a repeating timer's callback is running.
+    pub(crate) task: Option<TimerTask>,
+    pub(crate) cancelled: Arc<AtomicBool>,
+}
+
+pub(crate) struct Expired {
+    pub(crate) id: TimerId,
+    pub(crate) when: Instant,
+    pub(crate) task: TimerTask,
+}
+
+impl Timer {
+    pub(crate) fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Acquire)
+    }
+}
+
+pub(crate) enum TimerQueue {
+    Heap(heap::TimerHeap),
+    Wheel(wheel::TimerWheel),
+}
+
+impl TimerQueue {
+    pub(crate) fn new(kind: TimerKind) -> Self {
+        match kind {
+            TimerKind::Heap => Self::Heap(heap::TimerHeap::new()),
+            TimerKind::Wheel => Self::Wheel(wheel::TimerWheel::new()),
+        }
+    }
+
+    pub(crate) fn insert(&mut self, when: Instant, task: TimerTask) -> (TimerId, Arc<AtomicBool>) {
+        match self {
+            TimerQueue::Heap(q) => q.insert(when, task),
+            TimerQueue::Wheel(q) => q.insert(when, task),
+        }
+    }
+
+    pub(crate) fn insert_with(
+        &mut self,
+        when: Instant,
+        task: TimerTask,
+        cancelled: Arc<AtomicBool>,
+    ) -> TimerId {
+        match self {
+            TimerQueue::Heap(q) => q.insert_with(when, task, cancelled),
+            TimerQueue::Wheel(q) => q.insert_with(when, task, cancelled),
+        }
+    }
+
+    pub(crate) fn cancel(&mut self, id: TimerId) -> bool {
+        match self {
+            TimerQueue::Heap(q) => q.cancel(id),
+            TimerQueue::Wheel(q) => q.cancel(id),
+        }
+    }
+
+    pub(crate) fn reset(&mut self, id: TimerId, when: Instant) -> bool {
+        match self {
+            TimerQueue::Heap(q) => q.reset(id, when),
+            TimerQueue::Wheel(q) => q.reset(id, when),
+        }
+    }
+
+    pub(crate) fn next_deadline(&mut self) -> Option<Instant> {
+        match self {
+            TimerQueue::Heap(q) => q.next_deadline(),
+            TimerQueue::Wheel(q) => q.next_deadline(),
+        }
+    }
+
+    pub(crate) fn pop_expired(&mut self, now: Instant) -> Option<Expired> {
+        match self {
+            TimerQueue::Heap(q) => q.pop_expired(now),
+            TimerQueue::Wheel(q) => q.pop_expired(now),
+        }
+    }
+
+    /// Hands a fired repeating timer its task back and schedules it at
+    /// `when`. Returns `false` if it was cancelled while running.
+    pub(crate) fn rearm(&mut self, id: TimerId, task: TimerTask, when: Instant) -> bool {
+        let timers = match self {
+            TimerQueue::Heap(q) => q.timers_mut(),
+            TimerQueue::Wheel(q) => q.timers_mut(),
+        };
+        let Some(t) = timers.get_mut(id.0) else {
+            return false;
+        };
+        t.task = Some(task);
+        self.reset(id, when)
+    }
+}
+
+// Once timers leave the store when they fire; repeating ones keep their slot
+// (and so their `TimerId`) with the task taken out until `rearm`.
+fn take_expired(timers: &mut Slab<Timer>, id: TimerId) -> Option<Expired> {
+    let t = timers.get_mut(id.0)?;
+    let when = t.when;
+    let task = t.task.take()?;
+    if let TimerTask::Once(_) = task {
+        timers.remove(id.0);
+    }
+    Some(Expired { id, when, task })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn once() -> TimerTask {
+        TimerTask::Once(Box::new(|_| {}))
+    }
+
+    // Deterministic xorshift, so failures reproduce.
+    fn rng(seed: &mut u64) -> u64 {
+        *seed ^= *seed << 13;
+        *seed ^= *seed >> 7;
+        *seed ^= *seed << 17;
+        *seed
+    }
+
+    // Schedules the same timers in both stores, cancelling and resetting
+    // some, and returns the ids in firing order.
+    fn fire_all(kind: TimerKind, start: Instant) -> Vec<TimerId> {
+        let mut q = TimerQueue::new(kind);
+        let mut seed = 0x2545_f491_4f6c_dd1d;
+        // Up to ~10h, so entries land on every level and in the overflow.
+        let at = |seed: &mut u64| {
+            let range = [50, 5_000, 300_000, 20_000_000, 36_000_000][(rng(seed) % 5) as usize];
+            start + Duration::from_millis(rng(seed) % range)
+        };
+        let mut ids = Vec::new();
+        for _ in 0..2000 {
+            let when = at(&mut seed);
+            ids.push(q.insert(when, once()));
+        }
+        let mut deadlines = Vec::new();
+        for (id, cancelled) in &ids {
+            match rng(&mut seed) % 5 {
+                0 => assert!(q.cancel(*id)),
+                1 => cancelled.store(true, Ordering::Release),
+                2 => {
+                    let when = at(&mut seed);
+                    assert!(q.reset(*id, when));
+                    deadlines.push(when);
+                }
+                _ => {}
+            }
+        }
+        deadlines.sort();
+
+        let mut fired = Vec::new();
+        let mut last = start;
+        for now in deadlines
+            .into_iter()
+            .chain([start + Duration::from_secs(86_400)])
+        {
+            while let Some(e) = q.pop_expired(now) {
+                assert!(e.when <= now && e.when >= last);
+                last = e.when;
+                fired.push(e.id);
+            }
+        }
+        assert_eq!(q.next_deadline(), None);
+        fired
+    }
+
+    #[test]
+    fn wheel_fires_in_the_same_order_as_the_heap() {
+        let start = Instant::now();
+        let heap = fire_all(TimerKind::Heap, start);
+        let wheel = fire_all(TimerKind::Wheel, start);
+        assert!(heap.len() > 1000);
+        assert_eq!(heap, wheel);
+    }
+
+    #[test]
+    fn timer_never_fires_before_its_deadline() {
+        for kind in [TimerKind::Heap, TimerKind::Wheel] {
+            let mut q = TimerQueue::new(kind);
+            let when = Instant::now() + Duration::from_micros(1500);
+            q.insert(when, once());
+            assert!(q.pop_expired(when - Duration::from_nanos(1)).is_none());
+            assert_eq!(q.next_deadline(), Some(when));
+            assert!(q.pop_expired(when).is_some());
+        }
+    }
+
+    #[test]
+    fn rearmed_repeating_timer_keeps_its_id() {
+        for kind in [TimerKind::Heap, TimerKind::Wheel] {
+            let mut q = TimerQueue::new(kind);
+            let start = Instant::now();
+            let period = Duration::from_millis(10);
+            let task = TimerTask::Repeating {
+                period,
+                behavior: MissedTickBehavior::Burst,
+                f: Box::new(|_| {}),
+            };
+            let (id, _) = q.insert(start + period, task);
+            for tick in 1..=3u32 {
+                let now = start + period * tick;
+                let e = q.pop_expired(now).expect("repeating timer fired");
+                assert_eq!(e.id, id);
+                assert!(q.rearm(id, e.task, now + period));
+            }
+            assert!(q.cancel(id));
+            assert!(q.pop_expired(start + period * 10).is_none());
+        }
+    }
+}
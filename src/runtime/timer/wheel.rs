@@ -0,0 +1,470 @@
+use super::{take_expired, Expired, Timer, TimerId, TimerTask};
+use crate::runtime::slab::Slab;
+
+use std::cmp::Reverse;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::time::Instant;
+
+const SLOT_BITS: u32 = 6;
+const SLOTS: usize = 1 << SLOT_BITS;
+const LEVELS: usize = 4;
+// `compact` walks every slot, then `overflow` and `due`, checking at most
+// `SWEEP_BUDGET` entries per call.
+const SWEEP_POSITIONS: usize = LEVELS * SLOTS + 2;
+const SWEEP_BUDGET: usize = 32;
+
+#[derive(Clone, Copy)]
+struct Entry {
+    when: Instant,
+    seq: u64,
+    id: TimerId,
+}
+
+/// Hierarchical timing wheel with 1ms ticks.
+///
+/// Level `n` has 64 slots of `64^n` ticks each; timers beyond the last level
+/// (~4.6h) wait in `overflow`. Cancel and reset only touch the slab, leaving
+/// stale wheel entries that are dropped when their slot is processed or
+/// swept a few slots at a time by `compact`. Expired entries move to `due`
+/// and are fired by exact deadline, so the tick size never makes a timer
+/// fire early.
+pub(crate) struct TimerWheel {
+    timers: Slab<Timer>,
+    levels: Vec<Vec<Vec<Entry>>>,
+    counts: [usize; LEVELS],
+    // One bit per non-empty slot, so lookups skip empty slots.
+    occupied: [u64; LEVELS],
+    overflow: Vec<Entry>,
+    due: Vec<Entry>,
+    due_sorted: bool,
+    // Earliest live entry, while known; `next_deadline` runs every loop
+    // iteration.
+    next: Option<Entry>,
+    next_known: bool,
+    // Cursor of the `compact` pass in progress: bucket and index in it.
+    sweeping: bool,
+    sweep_pos: usize,
+    sweep_idx: usize,
+    origin: Instant,
+    // Next tick that has not been processed yet.
+    current: u64,
+    seq: u64,
+}
+
+impl TimerWheel {
+    pub(crate) fn new() -> Self {
+        Self {
+            timers: Slab::new(),
+            levels: (0..LEVELS)
+                .map(|_| (0..SLOTS).map(|_| Vec::new()).collect())
+                .collect(),
+            counts: [0; LEVELS],
+            occupied: [0; LEVELS],
+            overflow: Vec::new(),
+            due: Vec::new(),
+            due_sorted: true,
+            next: None,
+            next_known: true,
+            sweeping: false,
+            sweep_pos: 0,
+            sweep_idx: 0,
+            origin: Instant::now(),
+            current: 0,
+            seq: 0,
+        }
+    }
+
+    pub(crate) fn insert(&mut self, when: Instant, task: TimerTask) -> (TimerId, Arc<AtomicBool>) {
+        let cancelled = Arc::new(AtomicBool::new(false));
+        (self.insert_with(when, task, cancelled.clone()), cancelled)
+    }
+
+    pub(crate) fn insert_with(
+        &mut self,
+        when: Instant,
+        task: TimerTask,
+        cancelled: Arc<AtomicBool>,
+    ) -> TimerId {
+        let seq = self.next_seq();
+        let id = TimerId(self.timers.insert(Timer {
+            when,
+            seq,
+            task: Some(task),
+            cancelled,
+        }));
+        let e = Entry { when, seq, id };
+        self.place(e);
+        self.note_earlier(e);
+        id
+    }
+
+    pub(crate) fn cancel(&mut self, id: TimerId) -> bool {
+        let removed = self.timers.remove(id.0).is_some();
+        if self.next.is_some_and(|n| n.id == id) {
+            self.next_known = false;
+        }
+        self.compact();
+        removed
+    }
+
+    pub(crate) fn reset(&mut self, id: TimerId, when: Instant) -> bool {
+        let seq = self.next_seq();
+        let Some(t) = self.timers.get_mut(id.0) else {
+            return false;
+        };
+        t.when = when;
+        t.seq = seq;
+        let e = Entry { when, seq, id };
+        self.place(e);
+        if self.next.is_some_and(|n| n.id == id) {
+            self.next_known = false;
+        }
+        self.note_earlier(e);
+        self.compact();
+        true
+    }
+
+    pub(crate) fn next_deadline(&mut self) -> Option<Instant> {
+        if self.next_known {
+            match self.next {
+                Some(e) if keep(&mut self.timers, &e) => return Some(e.when),
+                None => return None,
+                Some(_) => {}
+            }
+        }
+        self.next = self.find_next();
+        self.next_known = true;
+        self.next.map(|e| e.when)
+    }
+
+    pub(crate) fn pop_expired(&mut self, now: Instant) -> Option<Expired> {
+        self.advance(self.tick_of(now));
+        loop {
+            let e = self.peek_due()?;
+            if e.when > now {
+                return None;
+            }
+            self.due.pop();
+            if let Some(expired) = take_expired(&mut self.timers, e.id) {
+                self.next_known = false;
+                return Some(expired);
+            }
+        }
+    }
+
+    pub(crate) fn timers_mut(&mut self) -> &mut Slab<Timer> {
+        &mut self.timers
+    }
+
+    fn next_seq(&mut self) -> u64 {
+        let seq = self.seq;
+        self.seq = self.seq.wrapping_add(1);
+        seq
+    }
+
+    // Keeps the cached earliest entry valid across an insert or reset.
+    fn note_earlier(&mut self, e: Entry) {
+        if self.next_known && self.next.is_none_or(|n| (e.when, e.seq) < (n.when, n.seq)) {
+            self.next = Some(e);
+        }
+    }
+
+    fn find_next(&mut self) -> Option<Entry> {
+        if let Some(e) = self.peek_due() {
+            return Some(e);
+        }
+
+        // Every entry on a lower level expires before any entry on a higher
+        // one, so the first live slot found holds the earliest deadline.
+        for level in 0..LEVELS {
+            let start = slot_of(self.current, level);
+            let mut bits = self.occupied[level] & (!0u64 << start);
+            while bits != 0 {
+                let slot = bits.trailing_zeros() as usize;
+                bits &= bits - 1;
+                if let Some(e) = self.sweep_slot(level, slot) {
+                    return Some(e);
+                }
+            }
+        }
+
+        let mut overflow = std::mem::take(&mut self.overflow);
+        overflow.retain(|e| keep(&mut self.timers, e));
+        let min = overflow.iter().min_by_key(|e| (e.when, e.seq)).copied();
+        self.overflow = overflow;
+        min
+    }
+
+    // Drops stale entries from a slot and returns its earliest live one.
+    fn sweep_slot(&mut self, level: usize, slot: usize) -> Option<Entry> {
+        let mut bucket = std::mem::take(&mut self.levels[level][slot]);
+        let before = bucket.len();
+        bucket.retain(|e| keep(&mut self.timers, e));
+        self.counts[level] -= before - bucket.len();
+        if bucket.is_empty() {
+            self.occupied[level] &= !(1 << slot);
+        }
+        let min = bucket.iter().min_by_key(|e| (e.when, e.seq)).copied();
+        self.levels[level][slot] = bucket;
+        min
+    }
+
+    fn tick_of(&self, when: Instant) -> u64 {
+        when.saturating_duration_since(self.origin).as_millis() as u64
+    }
+
+    fn place(&mut self, e: Entry) {
+        let tick = self.tick_of(e.when);
+        if tick < self.current {
+            self.due.push(e);
+            self.due_sorted = false;
+            return;
+        }
+        let diff = tick ^ self.current;
+        let level = if diff == 0 {
+            0
+        } else {
+            ((63 - diff.leading_zeros()) / SLOT_BITS) as usize
+        };
+        if level >= LEVELS {
+            self.overflow.push(e);
+            return;
+        }
+        let slot = slot_of(tick, level);
+        self.levels[level][slot].push(e);
+        self.counts[level] += 1;
+        self.occupied[level] |= 1 << slot;
+    }
+
+    fn advance(&mut self, target: u64) {
+        while self.current <= target {
+            let lowest = (0..LEVELS).find(|&l| self.counts[l] > 0).unwrap_or(LEVELS);
+            if lowest > 0 {
+                // Nothing lives below `lowest`, so skip straight to the next
+                // tick where that level cascades.
+                let span = 1u64 << (lowest as u32 * SLOT_BITS);
+                let next = ((self.current | (span - 1)) + 1).min(target + 1);
+                self.current = next;
+                self.cascade(next);
+                continue;
+            }
+
+            let slot = slot_of(self.current, 0);
+            let expired = std::mem::take(&mut self.levels[0][slot]);
+            self.counts[0] -= expired.len();
+            self.occupied[0] &= !(1 << slot);
+            if !expired.is_empty() {
+                self.due.extend(expired);
+                self.due_sorted = false;
+            }
+            self.current += 1;
+            self.cascade(self.current);
+        }
+    }
+
+    fn cascade(&mut self, tick: u64) {
+        for level in 1..LEVELS {
+            let mask = (1u64 << (level as u32 * SLOT_BITS)) - 1;
+            if tick & mask != 0 {
+                return;
+            }
+            let slot = slot_of(tick, level);
+            let entries = std::mem::take(&mut self.levels[level][slot]);
+            self.counts[level] -= entries.len();
+            self.occupied[level] &= !(1 << slot);
+            for e in entries {
+                if keep(&mut self.timers, &e) {
+                    self.place(e);
+                }
+            }
+        }
+        let mask = (1u64 << (LEVELS as u32 * SLOT_BITS)) - 1;
+        if tick & mask == 0 {
+            for e in std::mem::take(&mut self.overflow) {
+                if keep(&mut self.timers, &e) {
+                    self.place(e);
+                }
+            }
+        }
+    }
+
+    fn peek_due(&mut self) -> Option<Entry> {
+        if !self.due_sorted {
+            // Latest first, so the earliest deadline pops off the back.
+            self.due.sort_unstable_by_key(|e| Reverse((e.when, e.seq)));
+            self.due_sorted = true;
+        }
+        while let Some(e) = self.due.last() {
+            if keep(&mut self.timers, e) {
+                return Some(*e);
+            }
+            self.due.pop();
+        }
+        None
+    }
+
+    // Mirrors `TimerHeap::compact`: idle timeouts reset on every read would
+    // otherwise pile up stale entries in far slots. Once stale entries
+    // outnumber live ones 2:1, a pass over the whole wheel starts and advances
+    // by `SWEEP_BUDGET` entries per call, so no cancel or reset pays for more.
+    fn compact(&mut self) {
+        if !self.sweeping {
+            let total = self.counts.iter().sum::<usize>() + self.overflow.len() + self.due.len();
+            if total <= 64 || total <= self.timers.len() * 2 {
+                return;
+            }
+            self.sweeping = true;
+        }
+        let mut budget = SWEEP_BUDGET;
+        while budget > 0 {
+            let pos = self.sweep_pos;
+            if pos == SWEEP_POSITIONS {
+                self.sweeping = false;
+                self.sweep_pos = 0;
+                return;
+            }
+            let (level, slot) = (pos / SLOTS, pos % SLOTS);
+            let bucket = if pos < LEVELS * SLOTS {
+                if self.occupied[level] & (1 << slot) == 0 {
+                    // Jump to the next occupied slot, or the next level.
+                    let bits = self.occupied[level] & (!0u64 << slot);
+                    self.sweep_pos = match bits {
+                        0 => (level + 1) * SLOTS,
+                        _ => level * SLOTS + bits.trailing_zeros() as usize,
+                    };
+                    budget -= 1;
+                    continue;
+                }
+                &mut self.levels[level][slot]
+            } else if pos == LEVELS * SLOTS {
+                &mut self.overflow
+            } else {
+                &mut self.due
+            };
+            let mut removed = 0;
+            while self.sweep_idx < bucket.len() && budget > 0 {
+                budget -= 1;
+                if keep(&mut self.timers, &bucket[self.sweep_idx]) {
+                    self.sweep_idx += 1;
+                } else {
+                    bucket.swap_remove(self.sweep_idx);
+                    removed += 1;
+                }
+            }
+            let done = self.sweep_idx >= bucket.len();
+            let empty = bucket.is_empty();
+            if pos < LEVELS * SLOTS {
+                self.counts[level] -= removed;
+                if empty {
+                    self.occupied[level] &= !(1 << slot);
+                }
+            } else if pos > LEVELS * SLOTS && removed > 0 {
+                self.due_sorted = false;
+            }
+            if done {
+                self.sweep_pos += 1;
+                self.sweep_idx = 0;
+            }
+        }
+    }
+}
+
+fn slot_of(tick: u64, level: usize) -> usize {
+    ((tick >> (level as u32 * SLOT_BITS)) as usize) & (SLOTS - 1)
+}
+
+// Returns whether `e` still refers to a live timer. Timers cancelled through
+// their `TimerHandle` flag are removed from the slab here.
+fn keep(timers: &mut Slab<Timer>, e: &Entry) -> bool {
+    match timers.get(e.id.0) {
+        Some(t) if t.seq == e.seq => {
+            if t.is_cancelled() {
+                timers.remove(e.id.0);
+                return false;
+            }
+            true
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn once() -> TimerTask {
+        TimerTask::Once(Box::new(|_| {}))
+    }
+
+    fn stored(w: &TimerWheel) -> usize {
+        w.counts.iter().sum::<usize>() + w.overflow.len() + w.due.len()
+    }
+
+    #[test]
+    fn cancel_and_reset_hold_up_under_compaction() {
+        let mut w = TimerWheel::new();
+        let start = w.origin;
+        let ids: Vec<_> = (0..1000u64)
+            .map(|i| w.insert(start + Duration::from_secs(3600 + i), once()).0)
+            .collect();
+
+        // Idle timeouts pushed back on every read.
+        for round in 1..=50u64 {
+            for (i, id) in ids.iter().enumerate() {
+                let when = start + Duration::from_millis(60_000 * round + i as u64);
+                assert!(w.reset(*id, when));
+            }
+            assert!(stored(&w) <= 4 * w.timers.len() + 64, "round {round}");
+        }
+        for id in ids.iter().step_by(2) {
+            assert!(w.cancel(*id));
+            assert!(!w.cancel(*id));
+        }
+        assert_eq!(w.timers.len(), 500);
+
+        let end = start + Duration::from_secs(4 * 3600);
+        let mut fired = Vec::new();
+        while let Some(e) = w.pop_expired(end) {
+            assert_eq!(
+                e.when,
+                start + Duration::from_millis(3_000_000 + e.id.0.index() as u64)
+            );
+            fired.push(e.id);
+        }
+        let live: Vec<_> = ids.iter().skip(1).step_by(2).copied().collect();
+        assert_eq!(fired, live);
+        assert_eq!(w.timers.len(), 0);
+    }
+
+    #[test]
+    fn next_deadline_follows_cancel_and_reset() {
+        let mut w = TimerWheel::new();
+        let start = w.origin;
+        let (a, _) = w.insert(start + Duration::from_millis(5), once());
+        let (b, _) = w.insert(start + Duration::from_secs(90), once());
+        let (c, c_cancelled) = w.insert(start + Duration::from_secs(20_000), once());
+        assert_eq!(w.next_deadline(), Some(start + Duration::from_millis(5)));
+
+        w.cancel(a);
+        assert_eq!(w.next_deadline(), Some(start + Duration::from_secs(90)));
+
+        w.reset(c, start + Duration::from_secs(1));
+        assert_eq!(w.next_deadline(), Some(start + Duration::from_secs(1)));
+
+        // A handle flag is only noticed when the entry is looked at.
+        c_cancelled.store(true, std::sync::atomic::Ordering::Release);
+        assert_eq!(w.next_deadline(), Some(start + Duration::from_secs(90)));
+
+        w.reset(b, start + Duration::from_secs(30_000));
+        assert_eq!(w.next_deadline(), Some(start + Duration::from_secs(30_000)));
+        assert!(w.pop_expired(start + Duration::from_secs(29_999)).is_none());
+        assert_eq!(
+            w.pop_expired(start + Duration::from_secs(30_000))
+                .map(|e| e.id),
+            Some(b)
+        );
+        assert_eq!(w.next_deadline(), None);
+    }
+}
@@ -1,7 +1,23 @@
+use std::time::{Duration, Instant};
+
 #[derive(Debug, Clone, Copy)]
 pub enum BackendKind {
     Epoll,
     Poll,
+    /// io_uring `POLL_ADD` readiness; Linux 5.11+, fails with `Unsupported`
+    /// where io_uring is missing or disabled.
+    IoUring,
+}
+
+/// Timer store used by an `EventLoop`.
+///
+/// `Heap` is a binary heap with O(log n) insert; `Wheel` is a hierarchical
+/// timing wheel with 1ms ticks and O(1) insert, cancel and reset, suited to many
+/// connection timeouts that are mostly cancelled or reset before firing.
+#[derive(Debug, Clone, Copy)]
+pub enum TimerKind {
+    Heap,
+    Wheel,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -11,6 +27,42 @@ pub enum Interest {
     ReadWrite,
 }
 
+/// Registration flags applied on top of an `Interest`.
+///
+/// `exclusive` maps to `EPOLLEXCLUSIVE` and cannot be combined with `oneshot`
+/// or changed later through `modify_io`. The poll backend treats
+/// `edge_triggered` and `exclusive` as level-triggered and emulates `oneshot`
+/// by disarming the entry after it fires; re-arm it with `modify_io`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct IoMode {
+    pub edge_triggered: bool,
+    pub oneshot: bool,
+    pub exclusive: bool,
+}
+
+impl IoMode {
+    pub const LEVEL: IoMode = IoMode {
+        edge_triggered: false,
+        oneshot: false,
+        exclusive: false,
+    };
+    pub const EDGE: IoMode = IoMode {
+        edge_triggered: true,
+        oneshot: false,
+        exclusive: false,
+    };
+    pub const ONESHOT: IoMode = IoMode {
+        edge_triggered: false,
+        oneshot: true,
+        exclusive: false,
+    };
+    pub const EXCLUSIVE: IoMode = IoMode {
+        edge_triggered: false,
+        oneshot: false,
+        exclusive: true,
+    };
+}
+
 #[derive(Debug, Clone, Copy, Default)]
 pub struct Ready {
     pub readable: bool,
@@ -19,4 +71,179 @@ pub struct Ready {
     pub hup: bool,
 }
 
+/// Identifies one registration on an `EventLoop`.
+///
+/// The generation is bumped every time a slot is reused, so a token held by a
+/// previous owner of the same fd never matches the new registration. A slot
+/// whose generation runs out is retired instead of wrapping.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Token {
+    index: u32,
+    generation: u32,
+}
+
+impl Token {
+    pub(crate) fn new(index: u32, generation: u32) -> Self {
+        Self { index, generation }
+    }
+
+    pub(crate) fn index(self) -> u32 {
+        self.index
+    }
+
+    pub(crate) fn generation(self) -> u32 {
+        self.generation
+    }
+
+    /// Packs the token into a `u64`, e.g. for a poller's kernel user data.
+    pub fn to_u64(self) -> u64 {
+        ((self.generation as u64) << 32) | self.index as u64
+    }
+
+    pub fn from_u64(v: u64) -> Self {
+        Self {
+            index: v as u32,
+            generation: (v >> 32) as u32,
+        }
+    }
+}
+
+/// A signal number, with constants for the common ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SignalKind(i32);
+
+impl SignalKind {
+    pub const HANGUP: Self = Self(1);
+    pub const INTERRUPT: Self = Self(2);
+    pub const QUIT: Self = Self(3);
+    pub const PIPE: Self = Self(13);
+    pub const ALARM: Self = Self(14);
+    pub const TERMINATE: Self = Self(15);
+    pub const WINDOW_CHANGE: Self = Self(28);
+    #[cfg(target_os = "linux")]
+    pub const USER1: Self = Self(10);
+    #[cfg(target_os = "linux")]
+    pub const USER2: Self = Self(12);
+    #[cfg(target_os = "linux")]
+    pub const CHILD: Self = Self(17);
+    #[cfg(not(target_os = "linux"))]
+    pub const USER1: Self = Self(30);
+    #[cfg(not(target_os = "linux"))]
+    pub const USER2: Self = Self(31);
+    #[cfg(not(target_os = "linux"))]
+    pub const CHILD: Self = Self(20);
+
+    pub const fn from_raw(signo: i32) -> Self {
+        Self(signo)
+    }
+
+    pub const fn as_raw(self) -> i32 {
+        self.0
+    }
+}
+
+/// What a repeating timer does when the loop falls behind its schedule.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MissedTickBehavior {
+    /// Fire every missed tick back to back until caught up.
+    #[default]
+    Burst,
+    /// Fire once, then schedule the next tick a full period from now.
+    Delay,
+    /// Fire once, then continue on the original schedule.
+    Skip,
+}
+
+impl MissedTickBehavior {
+    pub(crate) fn next_deadline(
+        self,
+        deadline: Instant,
+        period: Duration,
+        now: Instant,
+    ) -> Instant {
+        let next = deadline + period;
+        if next > now {
+            return next;
+        }
+        match self {
+            MissedTickBehavior::Burst => next,
+            MissedTickBehavior::Delay => now + period,
+            MissedTickBehavior::Skip => {
+                let missed = now.duration_since(deadline).as_nanos() / period.as_nanos();
+                // Beyond what a Duration holds, fall back to `Delay`.
+                (missed + 1)
+                    .checked_mul(period.as_nanos())
+                    .and_then(|n| u64::try_from(n).ok())
+                    .and_then(|n| deadline.checked_add(Duration::from_nanos(n)))
+                    .unwrap_or(now + period)
+            }
+        }
+    }
+}
+
 pub(crate) type Task = Box<dyn FnOnce(&mut crate::runtime::EventLoop) + Send + 'static>;
+pub(crate) type RepeatingTask = Box<dyn FnMut(&mut crate::runtime::EventLoop) + Send + 'static>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PERIOD: Duration = Duration::from_millis(10);
+
+    #[test]
+    fn on_time_ticks_follow_the_schedule() {
+        let start = Instant::now();
+        for behavior in [
+            MissedTickBehavior::Burst,
+            MissedTickBehavior::Delay,
+            MissedTickBehavior::Skip,
+        ] {
+            let next = behavior.next_deadline(start, PERIOD, start + Duration::from_millis(3));
+            assert_eq!(next, start + PERIOD, "{behavior:?}");
+        }
+    }
+
+    #[test]
+    fn burst_fires_missed_ticks_back_to_back() {
+        let start = Instant::now();
+        let now = start + Duration::from_millis(35);
+        let next = MissedTickBehavior::Burst.next_deadline(start, PERIOD, now);
+        assert_eq!(next, start + PERIOD);
+    }
+
+    #[test]
+    fn delay_restarts_the_schedule_from_now() {
+        let start = Instant::now();
+        let now = start + Duration::from_millis(35);
+        let next = MissedTickBehavior::Delay.next_deadline(start, PERIOD, now);
+        assert_eq!(next, now + PERIOD);
+    }
+
+    #[test]
+    fn skip_keeps_the_original_schedule() {
+        let start = Instant::now();
+        let now = start + Duration::from_millis(35);
+        let next = MissedTickBehavior::Skip.next_deadline(start, PERIOD, now);
+        assert_eq!(next, start + Duration::from_millis(40));
+
+        // Landing exactly on a tick skips past it.
+        let now = start + Duration::from_millis(40);
+        let next = MissedTickBehavior::Skip.next_deadline(start, PERIOD, now);
+        assert_eq!(next, start + Duration::from_millis(50));
+    }
+
+    #[test]
+    fn skip_handles_tiny_periods_after_long_stalls() {
+        let start = Instant::now();
+        let period = Duration::from_nanos(1);
+        let now = start + Duration::from_secs(5000);
+        let next = MissedTickBehavior::Skip.next_deadline(start, period, now);
+        assert_eq!(next, now + period);
+    }
+
+    #[test]
+    fn token_round_trips_through_u64() {
+        let token = Token::new(7, u32::MAX);
+        assert_eq!(Token::from_u64(token.to_u64()), token);
+    }
+}
@@ -1,41 +1,100 @@
 use std::fs::File;
 use std::io::{self, Write};
 use std::os::raw::c_int;
+#[cfg(target_os = "linux")]
+use std::os::raw::c_uint;
 use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 
+use super::PollerWaker;
+
 #[derive(Clone)]
 pub(crate) struct Waker {
-    writer: Arc<File>,
+    inner: Arc<WakerInner>,
+}
+
+struct WakerInner {
+    target: WakeTarget,
+    // Set by the first wake after the loop last drained its shared queue, so
+    // a burst of posts costs one write until the loop catches up.
+    notified: AtomicBool,
 }
 
 impl Waker {
     pub(crate) fn wake(&self) -> io::Result<()> {
-        let _ = (&*self.writer).write(&[1u8]);
-        Ok(())
+        if self.inner.notified.swap(true, Ordering::SeqCst) {
+            return Ok(());
+        }
+        let res = match &self.inner.target {
+            WakeTarget::Fd(writer) => match (&**writer).write(&WAKE_BYTES) {
+                // A full eventfd counter or pipe is already readable.
+                Err(e) if e.kind() != io::ErrorKind::WouldBlock => Err(e),
+                _ => Ok(()),
+            },
+            WakeTarget::Poller(w) => w.wake(),
+        };
+        if res.is_err() {
+            // Nothing reached the loop, so the next wake has to try again.
+            self.inner.notified.store(false, Ordering::SeqCst);
+        }
+        res
+    }
+
+    /// Called by the loop right before it drains the shared task queue.
+    pub(crate) fn clear(&self) {
+        self.inner.notified.store(false, Ordering::SeqCst);
     }
 }
 
-pub(crate) fn make_waker() -> io::Result<(File, Waker)> {
-    let (reader, writer) = make_pipe()?;
+enum WakeTarget {
+    Fd(Arc<File>),
+    Poller(Arc<dyn PollerWaker>),
+}
+
+pub(crate) fn make_waker() -> io::Result<(Arc<File>, Waker)> {
+    let (reader, writer) = make_wake_fds()?;
     set_nonblocking(reader.as_raw_fd())?;
-    let waker = Waker {
-        writer: Arc::new(writer),
-    };
-    Ok((reader, waker))
+    Ok((reader, Waker::with_target(WakeTarget::Fd(writer))))
 }
 
-fn make_pipe() -> io::Result<(File, File)> {
-    let mut fds = [0 as c_int, 0 as c_int];
+pub(crate) fn poller_waker(waker: Arc<dyn PollerWaker>) -> Waker {
+    Waker::with_target(WakeTarget::Poller(waker))
+}
 
-    #[cfg(target_os = "linux")]
-    unsafe {
-        if pipe2(fds.as_mut_ptr(), O_CLOEXEC | O_NONBLOCK) < 0 {
-            return Err(io::Error::last_os_error());
+impl Waker {
+    fn with_target(target: WakeTarget) -> Self {
+        Self {
+            inner: Arc::new(WakerInner {
+                target,
+                notified: AtomicBool::new(false),
+            }),
         }
     }
+}
+
+// An eventfd counter is readable until read, and one 8-byte read resets it,
+// so the loop's drain loop works unchanged for both fd kinds.
+#[cfg(target_os = "linux")]
+const WAKE_BYTES: [u8; 8] = 1u64.to_ne_bytes();
+#[cfg(not(target_os = "linux"))]
+const WAKE_BYTES: [u8; 1] = [1u8];
+
+// The loop reads and handles write the same eventfd.
+#[cfg(target_os = "linux")]
+fn make_wake_fds() -> io::Result<(Arc<File>, Arc<File>)> {
+    let fd = unsafe { eventfd(0, EFD_CLOEXEC | EFD_NONBLOCK) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let file = Arc::new(unsafe { File::from_raw_fd(fd) });
+    Ok((file.clone(), file))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn make_wake_fds() -> io::Result<(Arc<File>, Arc<File>)> {
+    let mut fds = [0 as c_int, 0 as c_int];
 
-    #[cfg(not(target_os = "linux"))]
     unsafe {
         if pipe(fds.as_mut_ptr()) < 0 {
             return Err(io::Error::last_os_error());
@@ -44,10 +103,10 @@ fn make_pipe() -> io::Result<(File, File)> {
 
     let reader = unsafe { File::from_raw_fd(fds[0]) };
     let writer = unsafe { File::from_raw_fd(fds[1]) };
-    Ok((reader, writer))
+    Ok((Arc::new(reader), Arc::new(writer)))
 }
 
-fn set_nonblocking(fd: RawFd) -> io::Result<()> {
+pub(crate) fn set_nonblocking(fd: RawFd) -> io::Result<()> {
     unsafe {
         let flags = fcntl(fd as c_int, F_GETFL);
         if flags < 0 {
@@ -62,7 +121,7 @@ fn set_nonblocking(fd: RawFd) -> io::Result<()> {
 
 extern "C" {
     #[cfg(target_os = "linux")]
-    fn pipe2(fds: *mut c_int, flags: c_int) -> c_int;
+    fn eventfd(initval: c_uint, flags: c_int) -> c_int;
     #[cfg(not(target_os = "linux"))]
     fn pipe(fds: *mut c_int) -> c_int;
 
@@ -78,4 +137,6 @@ const O_NONBLOCK: c_int = 0o0004000;
 const O_NONBLOCK: c_int = 0x0004;
 
 #[cfg(target_os = "linux")]
-const O_CLOEXEC: c_int = 0o2000000;
+const EFD_NONBLOCK: c_int = O_NONBLOCK;
+#[cfg(target_os = "linux")]
+const EFD_CLOEXEC: c_int = 0o2000000;
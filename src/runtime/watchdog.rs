@@ -0,0 +1,168 @@
+use super::{TimerId, Token};
+
+use std::io;
+use std::os::unix::io::RawFd;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// What the loop is running, as reported by the watchdog.
+#[derive(Debug, Clone)]
+pub enum Activity {
+    Io {
+        token: Token,
+        fd: RawFd,
+    },
+    Timer(TimerId),
+    /// A task queued with `post` or through a `Handle`.
+    Task,
+    Hook,
+    Signal(i32),
+    /// A poll of an `Executor` task; `name` is set for `spawn_named` tasks.
+    Spawned {
+        id: u64,
+        name: Option<Arc<str>>,
+    },
+}
+
+/// A callback that ran longer than the watchdog threshold.
+#[derive(Debug, Clone)]
+pub struct Stall {
+    pub activity: Activity,
+    /// How long it had been running when detected. The callback may run on.
+    pub elapsed: Duration,
+}
+
+type StallHook = Box<dyn FnMut(&Stall) + Send + 'static>;
+
+pub(crate) struct Running {
+    activity: Activity,
+    start: Instant,
+    reported: bool,
+}
+
+struct State {
+    epoch: Instant,
+    running: Mutex<Option<Running>>,
+    // Nanoseconds since `epoch` at which the loop last entered or left the
+    // poller.
+    beat: AtomicU64,
+    waiting: AtomicBool,
+    stopped: AtomicBool,
+}
+
+impl State {
+    fn lock(&self) -> std::sync::MutexGuard<'_, Option<Running>> {
+        self.running.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Cloneable view of a loop's watchdog, usable from any thread.
+#[derive(Clone)]
+pub struct Watchdog {
+    state: Arc<State>,
+}
+
+impl Watchdog {
+    /// Time since the loop last showed it is making progress. Zero while it
+    /// is blocked in the poller, since an idle loop is healthy; keeps growing
+    /// once the loop stops running.
+    pub fn heartbeat_age(&self) -> Duration {
+        if self.state.waiting.load(Ordering::Acquire) {
+            return Duration::ZERO;
+        }
+        let beat = Duration::from_nanos(self.state.beat.load(Ordering::Acquire));
+        self.state.epoch.elapsed().saturating_sub(beat)
+    }
+}
+
+/// The loop's side: records activities and stops the thread on drop.
+pub(crate) struct ActiveWatchdog {
+    state: Arc<State>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl ActiveWatchdog {
+    pub(crate) fn start(threshold: Duration, hook: StallHook) -> io::Result<(Self, Watchdog)> {
+        let state = Arc::new(State {
+            epoch: Instant::now(),
+            running: Mutex::new(None),
+            beat: AtomicU64::new(0),
+            waiting: AtomicBool::new(false),
+            stopped: AtomicBool::new(false),
+        });
+        let thread_state = state.clone();
+        let thread = thread::Builder::new()
+            .name("evloop-watchdog".into())
+            .spawn(move || watch(thread_state, threshold, hook))?;
+        let watchdog = Watchdog {
+            state: state.clone(),
+        };
+        Ok((
+            Self {
+                state,
+                thread: Some(thread),
+            },
+            watchdog,
+        ))
+    }
+
+    /// Marks `activity` as running and returns what it interrupted, to be
+    /// handed back to `exit`.
+    pub(crate) fn enter(&self, activity: Activity) -> Option<Running> {
+        self.state.lock().replace(Running {
+            activity,
+            start: Instant::now(),
+            reported: false,
+        })
+    }
+
+    pub(crate) fn exit(&self, mut prev: Option<Running>) {
+        let mut running = self.state.lock();
+        if let (Some(p), Some(cur)) = (prev.as_mut(), running.as_ref()) {
+            // One stall is reported once, by its innermost activity.
+            p.reported |= cur.reported;
+        }
+        *running = prev;
+    }
+
+    pub(crate) fn beat(&self, waiting: bool) {
+        let now = self.state.epoch.elapsed().as_nanos() as u64;
+        self.state.beat.store(now, Ordering::Release);
+        self.state.waiting.store(waiting, Ordering::Release);
+    }
+}
+
+impl Drop for ActiveWatchdog {
+    fn drop(&mut self) {
+        self.state.stopped.store(true, Ordering::Release);
+        if let Some(thread) = self.thread.take() {
+            thread.thread().unpark();
+            let _ = thread.join();
+        }
+    }
+}
+
+fn watch(state: Arc<State>, threshold: Duration, mut hook: StallHook) {
+    let tick = (threshold / 4).max(Duration::from_millis(1));
+    while !state.stopped.load(Ordering::Acquire) {
+        thread::park_timeout(tick);
+        let stall = {
+            let mut running = state.lock();
+            match running.as_mut() {
+                Some(r) if !r.reported && r.start.elapsed() >= threshold => {
+                    r.reported = true;
+                    Some(Stall {
+                        activity: r.activity.clone(),
+                        elapsed: r.start.elapsed(),
+                    })
+                }
+                _ => None,
+            }
+        };
+        if let Some(stall) = stall {
+            hook(&stall);
+        }
+    }
+}